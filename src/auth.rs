@@ -0,0 +1,291 @@
+//! Role-based authorization.
+//!
+//! Every RPC is mapped to the roles allowed to call it. On top of that,
+//! non-admin callers may only touch jobs they own, where ownership is the
+//! certificate CN of the client that started the job.
+//!
+//! Roles are carried by the client certificate in a custom extension
+//! ([`OID_ROLE`]) whose value is a DER `UTF8String` of one or more
+//! comma-separated `Role=<name>` entries, e.g. `Role=User` or
+//! `Role=User,Role=Viewer`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use asn1_rs::{FromDer, Utf8String};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use x509_parser::certificate::X509Certificate;
+
+/// OID of the certificate extension carrying the client's roles.
+pub const OID_ROLE: &str = "1.3.6.1.4.1.12345.1.1.1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Role {
+    /// Full access to every job.
+    Admin,
+    /// Can start jobs and manage the jobs it owns.
+    User,
+    /// Read-only access to the jobs it owns.
+    Viewer,
+}
+
+impl FromStr for Role {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Admin" => Ok(Role::Admin),
+            "User" => Ok(Role::User),
+            "Viewer" => Ok(Role::Viewer),
+            other => Err(AuthError::UnknownRole(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Admin => "Admin",
+            Role::User => "User",
+            Role::Viewer => "Viewer",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The RPCs exposed by the `WorkFlow` service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rpc {
+    StartJob,
+    StopJob,
+    GetJobStatus,
+    StreamOutput,
+    ListJobs,
+    WatchJobs,
+}
+
+impl Rpc {
+    /// Roles allowed to call this RPC.
+    pub fn allowed_roles(&self) -> &'static [Role] {
+        match self {
+            Rpc::StartJob | Rpc::StopJob => &[Role::Admin, Role::User],
+            Rpc::GetJobStatus | Rpc::StreamOutput | Rpc::ListJobs | Rpc::WatchJobs => {
+                &[Role::Admin, Role::User, Role::Viewer]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("no client certificate")]
+    MissingCertificate,
+    #[error("invalid client certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("client certificate has no CN")]
+    MissingCommonName,
+    #[error("unknown role `{0}`")]
+    UnknownRole(String),
+    #[error("client certificate has no role extension")]
+    MissingRoleExtension,
+    #[error("malformed role extension: {0}")]
+    MalformedRoleExtension(String),
+    #[error("none of the roles [{roles}] may call {rpc:?}")]
+    RpcNotAllowed { rpc: Rpc, roles: String },
+    #[error("{cn} does not own job {job_id}")]
+    NotJobOwner { cn: String, job_id: String },
+    #[error("{cn} may not access the jobs of {owner}")]
+    NotOwner { cn: String, owner: String },
+    #[error("{cn} may not run jobs as root or with the root group")]
+    RootNotAllowed { cn: String },
+}
+
+/// Check that a caller holding `roles` may call `rpc`.
+pub fn authorize(rpc: Rpc, roles: &[Role]) -> Result<(), AuthError> {
+    if roles.iter().any(|role| rpc.allowed_roles().contains(role)) {
+        Ok(())
+    } else {
+        Err(AuthError::RpcNotAllowed {
+            rpc,
+            roles: roles
+                .iter()
+                .map(|role| role.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        })
+    }
+}
+
+/// Check that the caller identified by `cn` and `roles` may access a job
+/// owned by `owner`. Admins can access every job.
+pub fn authorize_job(cn: &str, roles: &[Role], job_id: &str, owner: &str) -> Result<(), AuthError> {
+    if roles.contains(&Role::Admin) || cn == owner {
+        Ok(())
+    } else {
+        Err(AuthError::NotJobOwner {
+            cn: cn.to_string(),
+            job_id: job_id.to_string(),
+        })
+    }
+}
+
+/// Check that the caller identified by `cn` and `roles` may access the jobs
+/// of `owner` as a whole. Admins can access everyone's jobs.
+pub fn authorize_owner(cn: &str, roles: &[Role], owner: &str) -> Result<(), AuthError> {
+    if roles.contains(&Role::Admin) || cn == owner {
+        Ok(())
+    } else {
+        Err(AuthError::NotOwner {
+            cn: cn.to_string(),
+            owner: owner.to_string(),
+        })
+    }
+}
+
+/// Extract the roles granted by the role extension of `cert`.
+pub fn roles_from_certificate(cert: &X509Certificate<'_>) -> Result<Vec<Role>, AuthError> {
+    let ext = cert
+        .extensions()
+        .iter()
+        .find(|ext| ext.oid.to_string() == OID_ROLE)
+        .ok_or(AuthError::MissingRoleExtension)?;
+    parse_role_extension(ext.value)
+}
+
+/// Decode the DER value of the role extension.
+pub fn parse_role_extension(value: &[u8]) -> Result<Vec<Role>, AuthError> {
+    let (rest, roles) = Utf8String::from_der(value)
+        .map_err(|e| AuthError::MalformedRoleExtension(e.to_string()))?;
+    if !rest.is_empty() {
+        return Err(AuthError::MalformedRoleExtension(
+            "trailing data after UTF8String".to_string(),
+        ));
+    }
+
+    let mut parsed = Vec::new();
+    for entry in roles.as_ref().split(',') {
+        let name = entry.trim().strip_prefix("Role=").ok_or_else(|| {
+            AuthError::MalformedRoleExtension(format!("`{}` is not Role=<name>", entry))
+        })?;
+        let role = name.parse::<Role>()?;
+        if !parsed.contains(&role) {
+            parsed.push(role);
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::ClientIdentity;
+
+    /// DER encoding of `s` as a UTF8String.
+    fn utf8_string(s: &str) -> Vec<u8> {
+        assert!(s.len() < 128, "long form lengths are not needed here");
+        let mut der = vec![0x0c, s.len() as u8];
+        der.extend_from_slice(s.as_bytes());
+        der
+    }
+
+    #[test]
+    fn parses_a_single_role() {
+        let roles = parse_role_extension(&utf8_string("Role=User")).unwrap();
+        assert_eq!(roles, [Role::User]);
+    }
+
+    #[test]
+    fn parses_several_roles_in_order_without_duplicates() {
+        let der = utf8_string("Role=Viewer, Role=Admin,Role=Viewer");
+        let roles = parse_role_extension(&der).unwrap();
+        assert_eq!(roles, [Role::Viewer, Role::Admin]);
+    }
+
+    #[test]
+    fn rejects_a_trailing_comma() {
+        let err = parse_role_extension(&utf8_string("Role=User,")).unwrap_err();
+        assert!(
+            matches!(err, AuthError::MalformedRoleExtension(_)),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_entries_without_the_role_prefix() {
+        let err = parse_role_extension(&utf8_string("User")).unwrap_err();
+        assert!(
+            matches!(err, AuthError::MalformedRoleExtension(_)),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_an_unknown_role() {
+        let err = parse_role_extension(&utf8_string("Role=User,Role=Root")).unwrap_err();
+        assert!(
+            matches!(err, AuthError::UnknownRole(ref role) if role == "Root"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_other_string_types() {
+        // PrintableString rather than UTF8String.
+        let mut der = utf8_string("Role=User");
+        der[0] = 0x13;
+        let err = parse_role_extension(&der).unwrap_err();
+        assert!(
+            matches!(err, AuthError::MalformedRoleExtension(_)),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut der = utf8_string("Role=User");
+        der.push(0);
+        let err = parse_role_extension(&der).unwrap_err();
+        assert!(
+            matches!(err, AuthError::MalformedRoleExtension(_)),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let der = utf8_string("Role=User");
+        let err = parse_role_extension(&der[..der.len() - 1]).unwrap_err();
+        assert!(
+            matches!(err, AuthError::MalformedRoleExtension(_)),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn reads_the_bundled_client_certificate() {
+        let pem = include_bytes!("../certs/client.crt");
+        let der = rustls_pemfile::certs(&mut &pem[..])
+            .unwrap()
+            .pop()
+            .expect("no certificate in certs/client.crt");
+        let identity = ClientIdentity::from_der(&der).unwrap();
+        assert_eq!(identity.cn, "Alice");
+        assert_eq!(identity.roles, [Role::User]);
+    }
+
+    #[test]
+    fn non_admins_only_reach_their_own_jobs() {
+        assert!(authorize_job("Alice", &[Role::User], "job", "Alice").is_ok());
+        assert!(authorize_job("Alice", &[Role::User], "job", "Bob").is_err());
+        assert!(authorize_job("Root", &[Role::Admin], "job", "Bob").is_ok());
+        assert!(authorize(Rpc::StartJob, &[Role::Viewer]).is_err());
+        assert!(authorize(Rpc::ListJobs, &[Role::Viewer]).is_ok());
+    }
+}
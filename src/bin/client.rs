@@ -1,14 +1,15 @@
 use clap::Args;
 use clap::{Parser, Subcommand};
+use easy_workflow_demo::labels;
+use easy_workflow_demo::tls::{self, TlsPolicy, TlsSettings};
 use easy_workflow_demo::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-use tonic::{
-    transport::{Channel, ClientTlsConfig},
-    Request,
-};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tonic::{transport::Channel, Code, Request, Status};
 // Import the generated proto code
 pub mod demo {
     // tonic::include_proto!("demo");
@@ -16,8 +17,18 @@ pub mod demo {
     include!("../generated/demo.rs");
 }
 
+use demo::WatchJobsRequest;
 use demo::{work_flow_client::WorkFlowClient, Entrypoint};
-use demo::{EnvironmentVariables, JobStatusRequest, Quota};
+use demo::{EnvPolicy, EnvironmentVariables, JobStatusRequest, Quota, StartJobRequest};
+use demo::{
+    Isolation, JobState, ListJobsRequest, OutputChunk, OutputStream, StopJobRequest, StopOutcome,
+    StreamOutputRequest,
+};
+
+/// Delay before the first attempt to resume a broken output stream, doubled
+/// for every further attempt in a row.
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(8);
 
 /// Easy Workflow CLI - A command line tool for managing workflow jobs
 #[derive(Parser)]
@@ -48,6 +59,14 @@ struct CertArgs {
     /// Path to client private key file
     #[arg(long)]
     key: Option<String>,
+
+    /// TLS policy: `tls13` (TLS 1.3 only) or `modern` (TLS 1.3 and 1.2 AEAD suites)
+    #[arg(long, default_value = "tls13")]
+    tls_policy: TlsPolicy,
+
+    /// Cipher suites to offer, narrowing the TLS policy (e.g. TLS13_AES_256_GCM_SHA384)
+    #[arg(long, value_delimiter = ',')]
+    cipher_suites: Vec<String>,
 }
 
 /// Available commands for managing workflow jobs
@@ -55,32 +74,163 @@ struct CertArgs {
 enum Commands {
     /// Create and submit a new workflow job
     Create(CreateArgs),
+    /// Stop a running workflow job: SIGTERM, then SIGKILL after the grace period
+    Stop(StopArgs),
+    /// Get the status of a workflow job
+    Status(JobArgs),
+    /// Stream the output of a workflow job from its start
+    Logs(LogsArgs),
+    /// List workflow jobs, oldest first
+    List(ListArgs),
+    /// Print job state changes as they happen
+    Watch(WatchArgs),
+}
+
+/// Arguments for commands addressing an existing workflow job
+#[derive(Args, Debug)]
+struct JobArgs {
+    /// ID of the job, as returned by `create`
+    job_id: String,
+}
+
+/// Arguments for streaming the output of a workflow job
+#[derive(Args, Debug)]
+struct LogsArgs {
+    /// ID of the job, as returned by `create`
+    job_id: String,
+
+    /// Attempt whose output to stream, counting from 1 (0 for the latest)
+    #[arg(long, default_value = "0")]
+    attempt: u32,
+
+    /// Only output of this stream (both if unset)
+    #[arg(long, value_parser = ["stdout", "stderr"])]
+    stream: Option<String>,
+
+    /// Byte offset in the output to start at
+    #[arg(long, default_value = "0")]
+    offset: u64,
+
+    /// Start at the last N lines written so far instead (0 for the whole output)
+    #[arg(long, default_value = "0", conflicts_with = "offset")]
+    tail: u32,
+
+    /// Times in a row to reconnect and resume after losing the connection
+    #[arg(long, default_value = "10")]
+    max_reconnects: u32,
+
+    /// Print the task's stderr to stderr instead of interleaving it with its stdout
+    #[arg(long)]
+    separate: bool,
+
+    /// Prefix every line with the seconds since the attempt started
+    #[arg(long)]
+    timestamps: bool,
+}
+
+/// Arguments for listing workflow jobs
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Label selector, e.g. `team=infra,env!=prod`
+    #[arg(short = 'l', long, default_value = "")]
+    selector: String,
+
+    /// Only jobs in this state (queued, running, succeeded, failed, stopped, timed-out, retrying, lost); repeatable
+    #[arg(long = "state", value_parser = parse_job_state)]
+    states: Vec<JobState>,
+
+    /// Only jobs of this owner (certificate CN)
+    #[arg(long, default_value = "")]
+    owner: String,
+
+    /// Maximum number of jobs to print (0 for the server default)
+    #[arg(long, default_value = "0")]
+    page_size: u32,
+
+    /// Token printed after the previous page
+    #[arg(long, default_value = "")]
+    page_token: String,
+}
+
+/// Arguments for watching job state changes
+#[derive(Args, Debug)]
+struct WatchArgs {
+    /// Label selector, e.g. `team=infra,env!=prod`
+    #[arg(short = 'l', long, default_value = "")]
+    selector: String,
+
+    /// Only this job; repeatable
+    #[arg(long = "job")]
+    job_ids: Vec<String>,
+
+    /// Token printed with the last change seen, to continue right after it
+    #[arg(long, default_value = "")]
+    resume_token: String,
+}
+
+fn parse_job_state(s: &str) -> Result<JobState> {
+    let name = format!("JOB_STATE_{}", s.to_uppercase().replace('-', "_"));
+    JobState::from_str_name(&name)
+        .filter(|state| *state != JobState::Unspecified)
+        .ok_or_else(|| anyhow::format_err!("unknown job state: {}", s))
+}
+
+/// Arguments for stopping a workflow job
+#[derive(Args, Debug)]
+struct StopArgs {
+    /// ID of the job, as returned by `create`
+    job_id: String,
+
+    /// Seconds to wait after SIGTERM before killing the job (0 for the server default)
+    #[arg(long, default_value = "0")]
+    grace_period: u32,
 }
 
 /// Arguments for creating a new workflow job
 #[derive(Args, Debug)]
 struct CreateArgs {
-    /// Command to execute
-    #[arg(long, required = true)]
-    cmd: String,
+    /// Shell command line to execute
+    #[arg(long, required_unless_present = "argv", conflicts_with = "argv")]
+    cmd: Option<String>,
+
+    /// Program and arguments to execute without a shell, after `--`
+    #[arg(last = true, value_name = "ARGV")]
+    argv: Vec<String>,
+
+    /// Run the first word after `--` as a shell command line, the rest
+    /// becoming its positional parameters
+    #[arg(long, requires = "argv")]
+    shell: bool,
+
+    /// Absolute directory to run in (server's working directory if unset)
+    #[arg(long)]
+    working_dir: Option<String>,
+
+    /// File to feed to the standard input of the task, `-` for this one's
+    #[arg(long)]
+    stdin: Option<PathBuf>,
+
+    /// Start from an empty environment instead of the server's
+    #[arg(long)]
+    clear_env: bool,
 
     /// Environment variables in format KEY=VALUE
     #[arg(long)]
     env: Vec<String>,
 
-    /// CPU quota (e.g., "1" for one core, "0.5" for half core)
+    /// CPU quota in cores (0 for unlimited)
     #[arg(long, default_value = "1")]
     cpu: u32,
 
-    /// Memory quota in MB
+    /// Memory quota in MB (0 for unlimited)
     #[arg(long, default_value = "1024")]
     memory: u32,
 
-    /// IO quota in MB
+    /// IO bandwidth quota in MB/s per disk (0 for unlimited)
     #[arg(long, default_value = "1024")]
     io: u32,
 
-    /// Task timeout in seconds
+    /// Task timeout in seconds (0 for no timeout)
     #[arg(long, default_value = "0")]
     timeout: u32,
 
@@ -89,7 +239,7 @@ struct CreateArgs {
     retry_count: u32,
 
     /// Task priority (-10 to 10, higher number means higher priority)
-    #[arg(long, default_value = "0")]
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
     priority: i32,
 
     /// Labels to attach to the task (format: KEY=VALUE)
@@ -99,6 +249,10 @@ struct CreateArgs {
     /// Annotations to attach to the task (format: KEY=VALUE)
     #[arg(long)]
     annotations: Vec<String>,
+
+    /// Isolation of the task from the host (server default if unset)
+    #[arg(long, value_parser = ["none", "namespaces"])]
+    isolation: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -136,30 +290,21 @@ impl Certs {
     }
 }
 
-async fn open_tls_client(certs: Certs) -> Result<WorkFlowClient<Channel>> {
+async fn open_tls_client(certs: Certs, settings: &TlsSettings) -> Result<WorkFlowClient<Channel>> {
     println!("certs: {:?}", certs);
     // Load client certificate and key
     let cert = tokio::fs::read(certs.crt).await?;
     let key = tokio::fs::read(certs.key).await?;
 
-    // Client identity (client certificate and key)
-    let client_identity = tonic::transport::Identity::from_pem(cert, key);
-
     // CA certificate to verify server
     let server_ca_cert = tokio::fs::read(certs.ca_crt).await?;
-    let server_ca_cert = tonic::transport::Certificate::from_pem(server_ca_cert);
 
     // Configure TLS
-    let tls_config = ClientTlsConfig::new()
-        .domain_name("localhost") // Must match the server's certificate CN
-        .identity(client_identity)
-        .ca_certificate(server_ca_cert);
-
-    // Create a channel with TLS configuration
-    let channel = Channel::from_static("https://localhost:50051")
-        .tls_config(tls_config)?
-        .connect()
-        .await?;
+    let tls_config = tls::client_config(&cert, &key, &server_ca_cert, settings)?;
+
+    // Create a channel with TLS configuration; the server name must match the
+    // server's certificate
+    let channel = tls::connect("localhost:50051", "localhost", tls_config).await?;
     Ok(WorkFlowClient::new(channel))
 }
 
@@ -179,6 +324,41 @@ impl TryFrom<String> for EnvironmentVariables {
     }
 }
 
+/// A `KEY=VALUE` label or annotation given on the command line.
+#[derive(Debug)]
+struct KeyValue {
+    key: String,
+    value: String,
+}
+
+impl TryFrom<String> for KeyValue {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        let (key, value) = value
+            .split_once('=')
+            .ok_or_else(|| anyhow::format_err!("invalid KEY=VALUE input: {}", value.as_str()))?;
+        labels::validate_key(key)?;
+        Ok(KeyValue {
+            key: key.into(),
+            value: value.into(),
+        })
+    }
+}
+
+/// Parse `KEY=VALUE` entries into a map, rejecting repeated keys.
+fn parse_key_values(entries: Vec<String>, kind: &str) -> Result<HashMap<String, String>> {
+    let mut map = HashMap::new();
+    for entry in entries {
+        let KeyValue { key, value } = KeyValue::try_from(entry)?;
+        if map.contains_key(&key) {
+            return Err(anyhow::format_err!("duplicate {} key: {}", kind, key));
+        }
+        map.insert(key, value);
+    }
+    Ok(map)
+}
+
 async fn handle_create(mut client: WorkFlowClient<Channel>, args: CreateArgs) -> Result<()> {
     let envs = args
         .env
@@ -196,10 +376,39 @@ async fn handle_create(mut client: WorkFlowClient<Channel>, args: CreateArgs) ->
         }
     }
     let envs = envs.into_iter().map(|e| e.unwrap()).collect::<Vec<_>>();
-    let request: Request<JobStatusRequest> = Request::new(JobStatusRequest {
+    let labels = parse_key_values(args.labels, "label")?;
+    for (key, value) in &labels {
+        labels::validate_label_value(key, value)?;
+    }
+    let annotations = parse_key_values(args.annotations, "annotation")?;
+    let isolation = match args.isolation.as_deref() {
+        Some("none") => Isolation::None,
+        Some("namespaces") => Isolation::Namespaces,
+        _ => Isolation::Unspecified,
+    };
+    let stdin = match args.stdin {
+        Some(path) if path.as_os_str() == "-" => {
+            let mut data = Vec::new();
+            tokio::io::stdin().read_to_end(&mut data).await?;
+            Some(data)
+        }
+        Some(path) => Some(tokio::fs::read(&path).await?),
+        None => None,
+    };
+    let env_policy = if args.clear_env {
+        EnvPolicy::Clear
+    } else {
+        EnvPolicy::Inherit
+    };
+    let request: Request<StartJobRequest> = Request::new(StartJobRequest {
         entrypoint: Some(Entrypoint {
-            cmd: args.cmd,
+            cmd: args.cmd.unwrap_or_default(),
             envs,
+            argv: args.argv,
+            shell: args.shell,
+            working_dir: args.working_dir.unwrap_or_default(),
+            stdin,
+            env_policy: env_policy.into(),
         }),
         quota: Some(Quota {
             cpu: args.cpu,
@@ -209,27 +418,324 @@ async fn handle_create(mut client: WorkFlowClient<Channel>, args: CreateArgs) ->
         timeout: args.timeout,
         retry_count: args.retry_count,
         priority: args.priority,
-        labels: args.labels,
-        annotations: args.annotations,
+        labels,
+        annotations,
+        isolation: isolation.into(),
     });
 
-    let response = client.get_job_status(request).await?;
+    let response = client.start_job(request).await?;
     println!("RESPONSE={:?}", response);
 
     let response = response.into_inner();
     println!("Server message with: {}", response.header.unwrap().message);
+    println!("Job ID: {}", response.job_id);
     Ok(())
 }
 
+async fn handle_stop(mut client: WorkFlowClient<Channel>, args: StopArgs) -> Result<()> {
+    let request = Request::new(StopJobRequest {
+        job_id: args.job_id,
+        grace_period_secs: args.grace_period,
+    });
+    let response = client.stop_job(request).await?.into_inner();
+    let outcome = response.outcome();
+    println!("Server message with: {}", response.header.unwrap().message);
+    println!("Outcome: {:?}", outcome);
+    Ok(())
+}
+
+async fn handle_status(mut client: WorkFlowClient<Channel>, args: JobArgs) -> Result<()> {
+    let request = Request::new(JobStatusRequest {
+        job_id: args.job_id,
+    });
+    let response = client.get_job_status(request).await?.into_inner();
+    println!("Job ID: {}", response.job_id);
+    println!("State: {:?}", response.state());
+    if let Some(queue_position) = response.queue_position {
+        println!("Queue position: {}", queue_position);
+    }
+    for (name, map) in [
+        ("Labels", &response.labels),
+        ("Annotations", &response.annotations),
+    ] {
+        if !map.is_empty() {
+            let sorted: BTreeMap<_, _> = map.iter().collect();
+            let entries: Vec<_> = sorted
+                .into_iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            println!("{}: {}", name, entries.join(", "));
+        }
+    }
+    println!("Isolation: {:?}", response.isolation());
+    println!("Attempt: {}", response.attempt);
+    if let Some(exit_code) = response.exit_code {
+        println!("Exit code: {}", exit_code);
+    }
+    if let Some(signal) = response.signal {
+        println!("Signal: {}", signal);
+    }
+    if response.stop_outcome() != StopOutcome::Unspecified {
+        println!("Stopped: {:?}", response.stop_outcome());
+    }
+    println!("Created at (ms since epoch): {}", response.created_at_ms);
+    if let Some(started_at_ms) = response.started_at_ms {
+        println!("Started at (ms since epoch): {}", started_at_ms);
+    }
+    if let Some(finished_at_ms) = response.finished_at_ms {
+        println!("Finished at (ms since epoch): {}", finished_at_ms);
+    }
+    if !response.attempts.is_empty() {
+        println!("Attempts:");
+        for attempt in &response.attempts {
+            let result = match (attempt.exit_code, attempt.signal) {
+                (Some(exit_code), _) => format!("exit code {}", exit_code),
+                (None, Some(signal)) => format!("signal {}", signal),
+                (None, None) => "unknown".to_string(),
+            };
+            println!(
+                "  #{} {:?}, {}, ran {} ms",
+                attempt.number,
+                attempt.state(),
+                result,
+                attempt.finished_at_ms - attempt.started_at_ms
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Writes output chunks to the terminal as they come in.
+#[derive(Debug)]
+struct OutputPrinter {
+    separate: bool,
+    timestamps: bool,
+    // Whether the next byte written to stdout or stderr starts a line.
+    stdout_at_line_start: bool,
+    stderr_at_line_start: bool,
+}
+
+impl OutputPrinter {
+    fn new(separate: bool, timestamps: bool) -> Self {
+        Self {
+            separate,
+            timestamps,
+            stdout_at_line_start: true,
+            stderr_at_line_start: true,
+        }
+    }
+
+    async fn print(&mut self, chunk: &OutputChunk) -> Result<()> {
+        let to_stderr = self.separate && chunk.stream() == OutputStream::Stderr;
+        let at_line_start = if to_stderr {
+            &mut self.stderr_at_line_start
+        } else {
+            &mut self.stdout_at_line_start
+        };
+        let mut text = Vec::with_capacity(chunk.data.len());
+        for line in chunk.data.split_inclusive(|byte| *byte == b'\n') {
+            if self.timestamps && *at_line_start {
+                let prefix = format!("[{:>10.3}] ", chunk.timestamp_ms as f64 / 1000.0);
+                text.extend_from_slice(prefix.as_bytes());
+            }
+            text.extend_from_slice(line);
+            *at_line_start = line.ends_with(b"\n");
+        }
+        if to_stderr {
+            let mut stderr = tokio::io::stderr();
+            stderr.write_all(&text).await?;
+            stderr.flush().await?;
+        } else {
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&text).await?;
+            stdout.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn handle_logs(mut client: WorkFlowClient<Channel>, args: LogsArgs) -> Result<()> {
+    let stream = match args.stream.as_deref() {
+        Some("stdout") => OutputStream::Stdout,
+        Some("stderr") => OutputStream::Stderr,
+        _ => OutputStream::Unspecified,
+    };
+    let mut request = StreamOutputRequest {
+        job_id: args.job_id,
+        attempt: args.attempt,
+        stream: stream.into(),
+        offset: args.offset,
+        tail_lines: args.tail,
+    };
+    let mut printer = OutputPrinter::new(args.separate, args.timestamps);
+    let mut reconnects = 0;
+    loop {
+        let offset = request.offset;
+        let err = match stream_output(&mut client, &mut request, &mut printer).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        // Only a stream that made progress earns a fresh set of reconnects.
+        if request.offset != offset {
+            reconnects = 0;
+        }
+        match err.downcast_ref::<Status>() {
+            Some(status) if is_transport_error(status) && reconnects < args.max_reconnects => {
+                let delay = RECONNECT_DELAY
+                    .saturating_mul(2u32.saturating_pow(reconnects))
+                    .min(MAX_RECONNECT_DELAY);
+                eprintln!(
+                    "lost the output stream ({}), resuming at offset {} in {:?}",
+                    status.message(),
+                    request.offset,
+                    delay
+                );
+                reconnects += 1;
+                tokio::time::sleep(delay).await;
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+/// Print the output `request` asks for until the stream ends, moving
+/// `request` past every chunk printed so that it resumes right after them.
+async fn stream_output(
+    client: &mut WorkFlowClient<Channel>,
+    request: &mut StreamOutputRequest,
+    printer: &mut OutputPrinter,
+) -> Result<()> {
+    let mut stream = client
+        .stream_output(Request::new(request.clone()))
+        .await?
+        .into_inner();
+    while let Some(chunk) = stream.message().await? {
+        printer.print(&chunk).await?;
+        // Stick to the attempt being printed, even once a newer one starts.
+        request.attempt = chunk.attempt;
+        request.offset = chunk.offset + chunk.data.len() as u64;
+        request.tail_lines = 0;
+    }
+    Ok(())
+}
+
+/// Whether `status` reports a broken connection rather than an error of
+/// the server.
+fn is_transport_error(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::Unknown | Code::Cancelled
+    )
+}
+
+async fn handle_list(mut client: WorkFlowClient<Channel>, args: ListArgs) -> Result<()> {
+    let request = Request::new(ListJobsRequest {
+        label_selector: args.selector,
+        states: args.states.into_iter().map(i32::from).collect(),
+        owner: args.owner,
+        page_token: args.page_token,
+        page_size: args.page_size,
+    });
+    let response = client.list_jobs(request).await?.into_inner();
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as i64)
+        .unwrap_or_default();
+    println!(
+        "{:<36}  {:<12}  {:<10}  {:>7}  {:>8}  {:>5}  {:<24}  COMMAND",
+        "JOB ID", "OWNER", "STATE", "ATTEMPT", "PRIORITY", "AGE", "LABELS"
+    );
+    for job in &response.jobs {
+        let labels: BTreeMap<_, _> = job.labels.iter().collect();
+        let labels: Vec<_> = labels
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        println!(
+            "{:<36}  {:<12}  {:<10}  {:>7}  {:>8}  {:>5}  {:<24}  {}",
+            job.job_id,
+            job.owner,
+            format!("{:?}", job.state()),
+            job.attempt,
+            job.priority,
+            format_age(now_ms - job.created_at_ms),
+            labels.join(","),
+            job.cmd
+        );
+    }
+    if !response.next_page_token.is_empty() {
+        println!("Next page token: {}", response.next_page_token);
+    }
+    Ok(())
+}
+
+async fn handle_watch(mut client: WorkFlowClient<Channel>, args: WatchArgs) -> Result<()> {
+    let request = Request::new(WatchJobsRequest {
+        label_selector: args.selector,
+        job_ids: args.job_ids,
+        resume_token: args.resume_token,
+    });
+    let mut stream = client.watch_jobs(request).await?.into_inner();
+    while let Some(change) = stream.message().await? {
+        let mut line = format!(
+            "[{}] {} {} {:?} attempt={}",
+            change.resume_token,
+            change.timestamp_ms,
+            change.job_id,
+            change.state(),
+            change.attempt
+        );
+        if let Some(exit_code) = change.exit_code {
+            line.push_str(&format!(" exit_code={}", exit_code));
+        }
+        if let Some(signal) = change.signal {
+            line.push_str(&format!(" signal={}", signal));
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Render a duration in milliseconds as a short age such as `42s` or `3h`.
+fn format_age(ms: i64) -> String {
+    let secs = ms.max(0) / 1000;
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     let certs = Certs::try_from(&cli.cert_args).await?;
-    let client = open_tls_client(certs).await?;
+    let tls_settings = TlsSettings {
+        policy: cli.cert_args.tls_policy,
+        cipher_suites: cli.cert_args.cipher_suites.clone(),
+    };
+    let client = open_tls_client(certs, &tls_settings).await?;
     match cli.command {
         Commands::Create(args) => {
             handle_create(client, args).await?;
         }
+        Commands::Stop(args) => {
+            handle_stop(client, args).await?;
+        }
+        Commands::Status(args) => {
+            handle_status(client, args).await?;
+        }
+        Commands::Logs(args) => {
+            handle_logs(client, args).await?;
+        }
+        Commands::List(args) => {
+            handle_list(client, args).await?;
+        }
+        Commands::Watch(args) => {
+            handle_watch(client, args).await?;
+        }
     }
 
     Ok(())
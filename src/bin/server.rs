@@ -1,8 +1,28 @@
+use clap::Parser;
+use easy_workflow_demo::auth::Rpc;
+use easy_workflow_demo::cgroup::Quota;
+use easy_workflow_demo::config::ServerConfig;
+use easy_workflow_demo::events::{EventError, ResumeToken};
+use easy_workflow_demo::identity::{self, ClientIdentity};
+use easy_workflow_demo::isolation::Isolation;
+use easy_workflow_demo::labels::Selector;
+use easy_workflow_demo::output::{Chunk, OutputStream};
+use easy_workflow_demo::state::{Attempt, JobState, StopOutcome};
+use easy_workflow_demo::store::{DiskStore, JobStore, MemoryStore};
+use easy_workflow_demo::tls;
+use easy_workflow_demo::users::UserMapping;
+use easy_workflow_demo::worker::{EnvPolicy, Job, JobFilter, JobSpec, Worker, WorkerError};
 use easy_workflow_demo::Result;
-use metrics::{counter, gauge, histogram};
-use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
-use rand::Rng;
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
 use tonic::{transport::Server, Request, Response, Status};
 use tracing::instrument;
 use tracing::{debug, info};
@@ -15,91 +35,526 @@ pub mod demo {
 
 use demo::work_flow_server::WorkFlowServer;
 use demo::{work_flow_server::WorkFlow, JobStatusRequest, JobStatusResponse};
+use demo::{JobStateChange, WatchJobsRequest};
+use demo::{ListJobsRequest, ListJobsResponse, OutputChunk, StreamOutputRequest};
+use demo::{StartJobRequest, StartJobResponse, StopJobRequest, StopJobResponse};
 
-const OID_ROLE: &str = "1.3.6.1.4.1.12345.1.1.1"; // Example OID for Role
+const OUTPUT_STREAM_BUFFER: usize = 16;
+const WATCH_STREAM_BUFFER: usize = 64;
 
 #[derive(Debug, Default)]
-pub struct WorkFlowService {}
+pub struct WorkFlowService {
+    worker: Worker,
+    // Applied to jobs that do not ask for a specific isolation, and the
+    // least any non-admin job gets.
+    isolation: Isolation,
+    users: UserMapping,
+}
+
+impl WorkFlowService {
+    pub fn new(worker: Worker) -> Self {
+        Self {
+            worker,
+            isolation: Isolation::default(),
+            users: UserMapping::default(),
+        }
+    }
+
+    pub fn with_isolation(mut self, isolation: Isolation) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    /// Run the jobs of each client as the account `users` maps it to.
+    pub fn with_user_mapping(mut self, users: UserMapping) -> Self {
+        self.users = users;
+        self
+    }
+}
+
+fn success_header() -> demo::ResponseHeader {
+    demo::ResponseHeader {
+        code: "0".to_string(),
+        message: "success".to_string(),
+    }
+}
+
+fn worker_error_to_status(err: WorkerError) -> Status {
+    match err {
+        WorkerError::InvalidSpec(_) | WorkerError::Label(_) | WorkerError::InvalidPageToken(_) => {
+            Status::invalid_argument(err.to_string())
+        }
+        WorkerError::NotFound(_) => Status::not_found(err.to_string()),
+        WorkerError::NotRunning(_) => Status::failed_precondition(err.to_string()),
+        WorkerError::Spawn(_) | WorkerError::Store(_) => Status::internal(err.to_string()),
+        WorkerError::Cgroup(_) => Status::failed_precondition(err.to_string()),
+        WorkerError::Capacity(_) => Status::resource_exhausted(err.to_string()),
+    }
+}
+
+/// Check that the caller, as authenticated by the interceptor, may call `rpc`.
+#[allow(clippy::result_large_err)]
+fn authorize<T>(request: &Request<T>, rpc: Rpc) -> std::result::Result<ClientIdentity, Status> {
+    let identity = request
+        .extensions()
+        .get::<ClientIdentity>()
+        .cloned()
+        .ok_or_else(|| Status::unauthenticated("request was not authenticated"))?;
+    identity
+        .authorize(rpc)
+        .map_err(|e| Status::permission_denied(e.to_string()))?;
+    Ok(identity)
+}
+
+/// Look up a job and check that the caller may access it.
+#[allow(clippy::result_large_err)]
+fn authorized_job(
+    worker: &Worker,
+    caller: &ClientIdentity,
+    job_id: &str,
+) -> std::result::Result<Arc<Job>, Status> {
+    let job = worker.get(job_id).map_err(worker_error_to_status)?;
+    caller
+        .authorize_job(job.id(), &job.spec().owner)
+        .map_err(|e| Status::permission_denied(e.to_string()))?;
+    Ok(job)
+}
+
+fn output_stream_to_proto(stream: OutputStream) -> demo::OutputStream {
+    match stream {
+        OutputStream::Stdout => demo::OutputStream::Stdout,
+        OutputStream::Stderr => demo::OutputStream::Stderr,
+    }
+}
+
+fn stop_outcome_to_proto(outcome: StopOutcome) -> demo::StopOutcome {
+    match outcome {
+        StopOutcome::ExitedCleanly => demo::StopOutcome::ExitedCleanly,
+        StopOutcome::Terminated => demo::StopOutcome::Terminated,
+        StopOutcome::Killed => demo::StopOutcome::Killed,
+        StopOutcome::Cancelled => demo::StopOutcome::Cancelled,
+    }
+}
+
+fn job_state_to_proto(state: JobState) -> demo::JobState {
+    match state {
+        JobState::Queued => demo::JobState::Queued,
+        JobState::Running => demo::JobState::Running,
+        JobState::Succeeded => demo::JobState::Succeeded,
+        JobState::Failed => demo::JobState::Failed,
+        JobState::Stopped => demo::JobState::Stopped,
+        JobState::TimedOut => demo::JobState::TimedOut,
+        JobState::Retrying => demo::JobState::Retrying,
+        JobState::Lost => demo::JobState::Lost,
+    }
+}
+
+fn event_error_to_status(err: EventError) -> Status {
+    match err {
+        EventError::Expired(_) | EventError::Restarted(_) => Status::out_of_range(format!(
+            "{}; list the jobs again and watch without a resume token",
+            err
+        )),
+        EventError::Unknown(_) => Status::invalid_argument(err.to_string()),
+    }
+}
+
+fn job_state_from_proto(state: demo::JobState) -> Option<JobState> {
+    match state {
+        demo::JobState::Unspecified => None,
+        demo::JobState::Queued => Some(JobState::Queued),
+        demo::JobState::Running => Some(JobState::Running),
+        demo::JobState::Succeeded => Some(JobState::Succeeded),
+        demo::JobState::Failed => Some(JobState::Failed),
+        demo::JobState::Stopped => Some(JobState::Stopped),
+        demo::JobState::TimedOut => Some(JobState::TimedOut),
+        demo::JobState::Retrying => Some(JobState::Retrying),
+        demo::JobState::Lost => Some(JobState::Lost),
+    }
+}
+
+fn isolation_to_proto(isolation: Isolation) -> demo::Isolation {
+    match isolation {
+        Isolation::None => demo::Isolation::None,
+        Isolation::Namespaces => demo::Isolation::Namespaces,
+    }
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn attempt_to_proto(attempt: &Attempt) -> demo::Attempt {
+    demo::Attempt {
+        number: attempt.number,
+        state: job_state_to_proto(attempt.state).into(),
+        exit_code: attempt.exit_code,
+        signal: attempt.signal,
+        started_at_ms: unix_millis(attempt.started_at),
+        finished_at_ms: unix_millis(attempt.finished_at),
+    }
+}
 
 #[tonic::async_trait]
 impl WorkFlow for WorkFlowService {
+    type StreamOutputStream =
+        Pin<Box<dyn Stream<Item = std::result::Result<OutputChunk, Status>> + Send>>;
+    type WatchJobsStream =
+        Pin<Box<dyn Stream<Item = std::result::Result<JobStateChange, Status>> + Send>>;
+
+    #[instrument(skip(self))]
+    async fn start_job(
+        &self,
+        request: Request<StartJobRequest>,
+    ) -> std::result::Result<Response<StartJobResponse>, Status> {
+        let c = counter!("create_job_count_total", "action" => "create", "method" => "POST");
+        c.increment(1);
+        let started = Instant::now();
+
+        let caller = authorize(&request, Rpc::StartJob)?;
+        let run_as = self
+            .users
+            .resolve(&caller.cn, &caller.roles)
+            .map_err(|e| Status::permission_denied(e.to_string()))?;
+
+        let request = request.into_inner();
+        let isolation = match request.isolation() {
+            demo::Isolation::Unspecified => self.isolation,
+            demo::Isolation::None => Isolation::None,
+            demo::Isolation::Namespaces => Isolation::Namespaces,
+        };
+        if isolation < self.isolation && !caller.is_admin() {
+            return Err(Status::permission_denied(format!(
+                "only admins may lower isolation below the server default `{}`",
+                self.isolation
+            )));
+        }
+        let entrypoint = request
+            .entrypoint
+            .ok_or_else(|| Status::invalid_argument("entrypoint is required"))?;
+        let env_policy = match entrypoint.env_policy() {
+            demo::EnvPolicy::Unspecified | demo::EnvPolicy::Inherit => EnvPolicy::Inherit,
+            demo::EnvPolicy::Clear => EnvPolicy::Clear,
+        };
+        let (argv, shell) = match (entrypoint.cmd.is_empty(), entrypoint.argv.is_empty()) {
+            (false, true) => (vec![entrypoint.cmd], true),
+            (true, false) => (entrypoint.argv, entrypoint.shell),
+            _ => {
+                return Err(Status::invalid_argument(
+                    "exactly one of cmd and argv must be set",
+                ))
+            }
+        };
+        let spec = JobSpec {
+            owner: caller.cn.clone(),
+            argv,
+            shell,
+            working_dir: Some(entrypoint.working_dir)
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+            stdin: entrypoint.stdin,
+            env_policy,
+            envs: entrypoint
+                .envs
+                .into_iter()
+                .map(|env| (env.key, env.value))
+                .collect(),
+            quota: request
+                .quota
+                .map(|quota| Quota {
+                    cpu: quota.cpu,
+                    memory: quota.memory,
+                    io: quota.io,
+                })
+                .unwrap_or_default(),
+            timeout: match request.timeout {
+                0 => None,
+                secs => Some(Duration::from_secs(secs.into())),
+            },
+            retry_count: request.retry_count,
+            priority: request.priority,
+            labels: request.labels.into_iter().collect(),
+            annotations: request.annotations.into_iter().collect(),
+            isolation,
+            run_as,
+        };
+        let result = self.worker.start(spec).await;
+
+        let histogram1 = histogram!("create_job_duration_seconds",
+                  "action" => "create", "status" => if result.is_ok() { "success" } else { "error" });
+        histogram1.record(started.elapsed().as_secs_f64());
+
+        let job_id = result.map_err(worker_error_to_status)?;
+        debug!("Client {} started job {}", caller.cn, job_id);
+
+        let response = StartJobResponse {
+            header: Some(success_header()),
+            job_id,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    #[instrument(skip(self))]
+    async fn stop_job(
+        &self,
+        request: Request<StopJobRequest>,
+    ) -> std::result::Result<Response<StopJobResponse>, Status> {
+        let caller = authorize(&request, Rpc::StopJob)?;
+        let request = request.into_inner();
+        let job = authorized_job(&self.worker, &caller, &request.job_id)?;
+        let grace_period = match request.grace_period_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs.into())),
+        };
+        let outcome = self
+            .worker
+            .stop(job.id(), grace_period)
+            .await
+            .map_err(worker_error_to_status)?;
+        debug!(
+            "Client {} stopped job {}: {:?}",
+            caller.cn,
+            job.id(),
+            outcome
+        );
+
+        let response = StopJobResponse {
+            header: Some(success_header()),
+            outcome: stop_outcome_to_proto(outcome).into(),
+        };
+        Ok(Response::new(response))
+    }
+
     #[instrument(skip(self))]
     async fn get_job_status(
         &self,
         request: Request<JobStatusRequest>,
     ) -> std::result::Result<Response<JobStatusResponse>, Status> {
-        let c = counter!("create_job_count_total", "action" => "create", "method" => "POST");
-        c.increment(1);
-        let mut rng = rand::rng();
-        let random = rng.random_range(1..10);
-        let gauge = gauge!("active_jobs");
-        gauge.set(random);
-        let random2 = rng.random_range(1..100) as f32 / 100.0;
-        let histogram1 = histogram!("create_job_duration_seconds", 
-                  "action" => "create", "status" => if random % 2 == 0 { "error" } else { "success" });
-        histogram1.record(random2);
-
-        // Extract client certificate
-        let client_cert_info = match request.peer_certs() {
-            Some(certs) => {
-                if let Some(cert) = certs.first() {
-                    // Parse the DER-encoded certificate using x509-parser
-                    match x509_parser::parse_x509_certificate(cert.as_ref()) {
-                        Ok((_, cert)) => {
-                            // Extract CN from subject
-                            let cn = cert
-                                .subject()
-                                .iter_common_name()
-                                .next()
-                                .and_then(|attr| attr.as_str().ok())
-                                .map(|s| s.to_string())
-                                .unwrap_or_else(|| "Unknown".to_string());
-
-                            // Try to find our custom extension
-                            let role = cert
-                                .extensions()
-                                .iter()
-                                .find(|ext| ext.oid.to_string() == OID_ROLE)
-                                .and_then(|ext| {
-                                    std::str::from_utf8(ext.value).ok().map(|s| s.to_string())
-                                })
-                                .unwrap_or_else(|| "No role found".to_string());
-
-                            (cn, role)
-                        }
-                        Err(_) => (
-                            "Failed to parse certificate".to_string(),
-                            "Unknown role".to_string(),
-                        ),
-                    }
-                } else {
-                    (
-                        "No certificate found".to_string(),
-                        "Unknown role".to_string(),
-                    )
-                }
+        let caller = authorize(&request, Rpc::GetJobStatus)?;
+        let request = request.into_inner();
+        let job = authorized_job(&self.worker, &caller, &request.job_id)?;
+
+        let status = job.status();
+        let response = JobStatusResponse {
+            header: Some(success_header()),
+            job_id: job.id().to_string(),
+            state: job_state_to_proto(status.state).into(),
+            exit_code: status.exit_code,
+            stop_outcome: status
+                .stop_outcome
+                .map(stop_outcome_to_proto)
+                .unwrap_or(demo::StopOutcome::Unspecified)
+                .into(),
+            signal: status.signal,
+            attempt: status.attempt,
+            created_at_ms: unix_millis(status.created_at),
+            started_at_ms: status.started_at.map(unix_millis),
+            finished_at_ms: status.finished_at.map(unix_millis),
+            attempts: status.attempts.iter().map(attempt_to_proto).collect(),
+            queue_position: self
+                .worker
+                .queue_position(job.id())
+                .map(|position| position as u32),
+            labels: job.spec().labels.clone().into_iter().collect(),
+            annotations: job.spec().annotations.clone().into_iter().collect(),
+            isolation: isolation_to_proto(job.spec().isolation).into(),
+        };
+        Ok(Response::new(response))
+    }
+
+    #[instrument(skip(self))]
+    async fn stream_output(
+        &self,
+        request: Request<StreamOutputRequest>,
+    ) -> std::result::Result<Response<Self::StreamOutputStream>, Status> {
+        let caller = authorize(&request, Rpc::StreamOutput)?;
+        let request = request.into_inner();
+        let job = authorized_job(&self.worker, &caller, &request.job_id)?;
+
+        let (attempt, output) = match request.attempt {
+            0 => job.output(),
+            attempt => job.attempt_output(attempt).map(|output| (attempt, output)),
+        }
+        .ok_or_else(|| match request.attempt {
+            0 => Status::failed_precondition(format!("job {} has not started yet", job.id())),
+            attempt => Status::not_found(format!("job {} has no attempt {}", job.id(), attempt)),
+        })?;
+        if output.is_discarded() {
+            return Err(Status::not_found(format!(
+                "output of job {} is no longer retained",
+                job.id()
+            )));
+        }
+
+        let stream = match request.stream() {
+            demo::OutputStream::Unspecified => None,
+            demo::OutputStream::Stdout => Some(OutputStream::Stdout),
+            demo::OutputStream::Stderr => Some(OutputStream::Stderr),
+        };
+
+        let offset = match request.tail_lines {
+            0 => request.offset,
+            _ if request.offset != 0 => {
+                return Err(Status::invalid_argument(
+                    "offset and tail_lines are mutually exclusive",
+                ))
             }
-            None => (
-                "No peer certificates".to_string(),
-                "Unknown role".to_string(),
-            ),
+            lines => output
+                .tail_offset(lines as usize, stream)
+                .map_err(|e| Status::internal(format!("failed to read job output: {}", e)))?,
         };
 
-        let (client_cn, client_role) = client_cert_info;
-        debug!("Client CN: {}", client_cn);
-        debug!("Client Role: {}", client_role);
+        // Each subscriber gets its own reader and forwarding task, so a slow
+        // client only ever holds up its own stream.
+        let reader = output.subscribe().with_offset(offset).with_stream(stream);
+        let (tx, rx) = mpsc::channel(OUTPUT_STREAM_BUFFER);
+        tokio::spawn(async move {
+            #[allow(clippy::result_large_err)]
+            let message = |chunk: Chunk| {
+                Ok(OutputChunk {
+                    stream: output_stream_to_proto(chunk.stream).into(),
+                    offset: chunk.offset,
+                    timestamp_ms: chunk.timestamp_ms,
+                    attempt,
+                    data: chunk.data,
+                })
+            };
+            reader.forward(&tx, message).await;
+            if tx.is_closed() {
+                debug!("output subscriber went away");
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_jobs(
+        &self,
+        request: Request<ListJobsRequest>,
+    ) -> std::result::Result<Response<ListJobsResponse>, Status> {
+        let caller = authorize(&request, Rpc::ListJobs)?;
+        let request = request.into_inner();
 
-        // Create response with certificate info
-        let response = demo::JobStatusResponse {
-            header: Some(demo::ResponseHeader {
-                code: "0".to_string(),
-                message: "success".to_string(),
-            }),
-            job_id: "1234567890".to_string(),
+        // Non-admins only ever see their own jobs.
+        let owner = match request.owner.as_str() {
+            "" if caller.is_admin() => None,
+            "" => Some(caller.cn.clone()),
+            owner => {
+                caller
+                    .authorize_owner(owner)
+                    .map_err(|e| Status::permission_denied(e.to_string()))?;
+                Some(owner.to_string())
+            }
         };
+        let states = request
+            .states()
+            .map(job_state_from_proto)
+            .collect::<Option<_>>()
+            .ok_or_else(|| Status::invalid_argument("job state must be specified"))?;
+        let filter = JobFilter {
+            selector: Selector::parse(&request.label_selector)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            states,
+            owner,
+        };
+        let page_token = Some(request.page_token.as_str()).filter(|token| !token.is_empty());
+        let page_size = Some(request.page_size as usize).filter(|size| *size != 0);
+        let page = self
+            .worker
+            .list(&filter, page_token, page_size)
+            .map_err(worker_error_to_status)?;
 
+        let jobs = page
+            .jobs
+            .iter()
+            .map(|job| {
+                let status = job.status();
+                demo::JobSummary {
+                    job_id: job.id().to_string(),
+                    owner: job.spec().owner.clone(),
+                    state: job_state_to_proto(status.state).into(),
+                    attempt: status.attempt,
+                    priority: job.spec().priority,
+                    created_at_ms: unix_millis(status.created_at),
+                    labels: job.spec().labels.clone().into_iter().collect(),
+                    cmd: job.spec().command_line(),
+                }
+            })
+            .collect();
+        let response = ListJobsResponse {
+            header: Some(success_header()),
+            jobs,
+            next_page_token: page.next_page_token.unwrap_or_default(),
+        };
         Ok(Response::new(response))
     }
+
+    #[instrument(skip(self))]
+    async fn watch_jobs(
+        &self,
+        request: Request<WatchJobsRequest>,
+    ) -> std::result::Result<Response<Self::WatchJobsStream>, Status> {
+        let caller = authorize(&request, Rpc::WatchJobs)?;
+        let request = request.into_inner();
+
+        let selector = Selector::parse(&request.label_selector)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let after = match request.resume_token.as_str() {
+            "" => None,
+            token => Some(
+                token
+                    .parse::<ResumeToken>()
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            ),
+        };
+        let mut reader = self.worker.watch(after).map_err(event_error_to_status)?;
+        let job_ids = request.job_ids;
+
+        let (tx, rx) = mpsc::channel(WATCH_STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let change = tokio::select! {
+                    change = reader.next() => change,
+                    _ = tx.closed() => break,
+                };
+                let change = match change {
+                    Ok(change) => change,
+                    Err(e) => {
+                        let _ = tx.send(Err(event_error_to_status(e))).await;
+                        break;
+                    }
+                };
+                // Non-admins only ever see their own jobs.
+                if caller.authorize_job(&change.job_id, &change.owner).is_err()
+                    || !(job_ids.is_empty() || job_ids.contains(&change.job_id))
+                    || !selector.matches(&change.labels)
+                {
+                    continue;
+                }
+                let message = JobStateChange {
+                    resume_token: change.token.to_string(),
+                    job_id: change.job_id.clone(),
+                    owner: change.owner.clone(),
+                    state: job_state_to_proto(change.state).into(),
+                    attempt: change.attempt,
+                    exit_code: change.exit_code,
+                    signal: change.signal,
+                    timestamp_ms: unix_millis(change.at),
+                    labels: change.labels.clone().into_iter().collect(),
+                };
+                if tx.send(Ok(message)).await.is_err() {
+                    break;
+                }
+            }
+            debug!("job watcher went away");
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
 }
 
 fn init_log() {
@@ -131,15 +586,15 @@ fn init_log() {
     }
 }
 
-fn setup_metrics_exporter() {
+fn setup_metrics_exporter(addr: SocketAddr, service_label: &str) {
     const EXPONENTIAL_SECONDS: &[f64] = &[
         0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
     ];
 
-    // Prometheus metrics server started on http://127.0.0.1:9091/metrics
+    // Prometheus metrics server started on http://<metrics_addr>/metrics
     PrometheusBuilder::new()
-        .with_http_listener(SocketAddr::from(([127, 0, 0, 1], 9091)))
-        .add_global_label("service", "my_awesome_service")
+        .with_http_listener(addr)
+        .add_global_label("service", service_label)
         .set_buckets_for_metric(
             Matcher::Full("create_job_duration_seconds".to_string()),
             EXPONENTIAL_SECONDS,
@@ -149,41 +604,312 @@ fn setup_metrics_exporter() {
         .expect("failed to install Prometheus recorder")
 }
 
+/// Easy Workflow server - runs workflow jobs on behalf of mTLS-authenticated clients
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Path to a JSON configuration file
+    #[arg(long, env = "WORKFLOW_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Path to the CA certificate used to verify clients
+    #[arg(long, env = "WORKFLOW_CA_CRT")]
+    ca_crt: Option<PathBuf>,
+
+    /// Path to the server certificate file
+    #[arg(long, env = "WORKFLOW_CRT")]
+    crt: Option<PathBuf>,
+
+    /// Path to the server private key file
+    #[arg(long, env = "WORKFLOW_KEY")]
+    key: Option<PathBuf>,
+
+    /// Address to listen on for gRPC, e.g. 127.0.0.1:50051
+    #[arg(long, env = "WORKFLOW_LISTEN_ADDR")]
+    listen_addr: Option<String>,
+
+    /// Address of the Prometheus metrics listener, e.g. 127.0.0.1:9091
+    #[arg(long, env = "WORKFLOW_METRICS_ADDR")]
+    metrics_addr: Option<String>,
+
+    /// Value of the global `service` label on exported metrics
+    #[arg(long, env = "WORKFLOW_SERVICE_LABEL")]
+    service_label: Option<String>,
+
+    /// TLS policy: `tls13` (TLS 1.3 only) or `modern` (TLS 1.3 and 1.2 AEAD suites)
+    #[arg(long, env = "WORKFLOW_TLS_POLICY")]
+    tls_policy: Option<String>,
+
+    /// Cipher suites to allow, narrowing the TLS policy (e.g. TLS13_AES_256_GCM_SHA384)
+    #[arg(long, env = "WORKFLOW_CIPHER_SUITES", value_delimiter = ',')]
+    cipher_suites: Vec<String>,
+
+    /// Delay before the first retry of a failed job, in milliseconds
+    #[arg(long, env = "WORKFLOW_RETRY_INITIAL_BACKOFF_MS")]
+    retry_initial_backoff_ms: Option<u64>,
+
+    /// Upper bound of the retry delay, in milliseconds
+    #[arg(long, env = "WORKFLOW_RETRY_MAX_BACKOFF_MS")]
+    retry_max_backoff_ms: Option<u64>,
+
+    /// Factor applied to the retry delay after every retry
+    #[arg(long, env = "WORKFLOW_RETRY_BACKOFF_MULTIPLIER")]
+    retry_backoff_multiplier: Option<f64>,
+
+    /// Relative random jitter of the retry delay, between 0 and 1
+    #[arg(long, env = "WORKFLOW_RETRY_JITTER")]
+    retry_jitter: Option<f64>,
+
+    /// Number of jobs allowed to run at the same time
+    #[arg(long, env = "WORKFLOW_MAX_RUNNING_JOBS")]
+    max_running_jobs: Option<usize>,
+
+    /// Seconds a queued job waits for each priority level it gains (0 disables aging)
+    #[arg(long, env = "WORKFLOW_PRIORITY_AGING_SECS")]
+    priority_aging_secs: Option<u64>,
+
+    /// CPU cores the quotas of running jobs may add up to (0 to not account CPU)
+    #[arg(long, env = "WORKFLOW_CAPACITY_CPU")]
+    capacity_cpu: Option<u32>,
+
+    /// Memory in MB the quotas of running jobs may add up to (0 to not account memory)
+    #[arg(long, env = "WORKFLOW_CAPACITY_MEMORY")]
+    capacity_memory: Option<u32>,
+
+    /// IO bandwidth in MB/s the quotas of running jobs may add up to (0 to not account IO)
+    #[arg(long, env = "WORKFLOW_CAPACITY_IO")]
+    capacity_io: Option<u32>,
+
+    /// Directory of the persistent job store (jobs are kept in memory and output in the temporary directory only if unset)
+    #[arg(long, env = "WORKFLOW_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Size in bytes at which the output of a job attempt is truncated (0 keeps everything)
+    #[arg(long, env = "WORKFLOW_MAX_OUTPUT_BYTES")]
+    max_output_bytes: Option<u64>,
+
+    /// Seconds the output of a finished job is kept (0 keeps it forever)
+    #[arg(long, env = "WORKFLOW_OUTPUT_RETENTION_SECS")]
+    output_retention_secs: Option<u64>,
+
+    /// Number of finished jobs that keep their output (0 keeps every job's)
+    #[arg(long, env = "WORKFLOW_MAX_RETAINED_OUTPUTS")]
+    max_retained_outputs: Option<usize>,
+
+    /// Isolation of jobs that do not ask for one: `none` or `namespaces`
+    #[arg(long, env = "WORKFLOW_ISOLATION")]
+    isolation: Option<String>,
+}
+
+impl Cli {
+    /// Load the config file, if any, and apply flags and environment variables on top.
+    fn into_config(self) -> Result<ServerConfig> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::from_file(path)?,
+            None => ServerConfig::default(),
+        };
+        if let Some(ca_crt) = self.ca_crt {
+            config.ca_crt = ca_crt;
+        }
+        if let Some(crt) = self.crt {
+            config.crt = crt;
+        }
+        if let Some(key) = self.key {
+            config.key = key;
+        }
+        if let Some(listen_addr) = self.listen_addr {
+            config.listen_addr = listen_addr;
+        }
+        if let Some(metrics_addr) = self.metrics_addr {
+            config.metrics_addr = metrics_addr;
+        }
+        if let Some(service_label) = self.service_label {
+            config.service_label = service_label;
+        }
+        if let Some(tls_policy) = self.tls_policy {
+            config.tls_policy = tls_policy;
+        }
+        if !self.cipher_suites.is_empty() {
+            config.cipher_suites = self.cipher_suites;
+        }
+        if let Some(retry_initial_backoff_ms) = self.retry_initial_backoff_ms {
+            config.retry_initial_backoff_ms = retry_initial_backoff_ms;
+        }
+        if let Some(retry_max_backoff_ms) = self.retry_max_backoff_ms {
+            config.retry_max_backoff_ms = retry_max_backoff_ms;
+        }
+        if let Some(retry_backoff_multiplier) = self.retry_backoff_multiplier {
+            config.retry_backoff_multiplier = retry_backoff_multiplier;
+        }
+        if let Some(retry_jitter) = self.retry_jitter {
+            config.retry_jitter = retry_jitter;
+        }
+        if let Some(max_running_jobs) = self.max_running_jobs {
+            config.max_running_jobs = max_running_jobs;
+        }
+        if let Some(priority_aging_secs) = self.priority_aging_secs {
+            config.priority_aging_secs = priority_aging_secs;
+        }
+        if let Some(capacity_cpu) = self.capacity_cpu {
+            config.capacity_cpu = capacity_cpu;
+        }
+        if let Some(capacity_memory) = self.capacity_memory {
+            config.capacity_memory = capacity_memory;
+        }
+        if let Some(capacity_io) = self.capacity_io {
+            config.capacity_io = capacity_io;
+        }
+        if let Some(data_dir) = self.data_dir {
+            config.data_dir = Some(data_dir);
+        }
+        if let Some(max_output_bytes) = self.max_output_bytes {
+            config.max_output_bytes = max_output_bytes;
+        }
+        if let Some(output_retention_secs) = self.output_retention_secs {
+            config.output_retention_secs = output_retention_secs;
+        }
+        if let Some(max_retained_outputs) = self.max_retained_outputs {
+            config.max_retained_outputs = max_retained_outputs;
+        }
+        if let Some(isolation) = self.isolation {
+            config.isolation = isolation;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let config = Cli::parse().into_config()?;
     init_log();
-    setup_metrics_exporter();
-    // Load certificates and private key files
-    let cert_path = "certs/server.crt";
-    let key_path = "certs/server.key";
-    let ca_cert_path = "certs/ca.crt";
+    setup_metrics_exporter(config.metrics_addr()?, &config.service_label);
 
     // Load server certificate and key
-    let cert = tokio::fs::read(cert_path).await?;
-    let key = tokio::fs::read(key_path).await?;
+    let cert = tokio::fs::read(&config.crt).await?;
+    let key = tokio::fs::read(&config.key).await?;
 
-    // Configure server TLS identity
-    let server_identity = tonic::transport::Identity::from_pem(cert, key);
-
-    // Configure CA certificate for client verification
-    let client_ca_cert = tokio::fs::read(ca_cert_path).await?;
-    let client_ca_cert = tonic::transport::Certificate::from_pem(client_ca_cert);
+    // Load CA certificate for client verification
+    let client_ca_cert = tokio::fs::read(&config.ca_crt).await?;
 
     // Create TLS configuration
-    let tls_config = tonic::transport::ServerTlsConfig::new()
-        .identity(server_identity)
-        .client_ca_root(client_ca_cert);
+    let tls_settings = config.tls_settings()?;
+    info!(
+        "TLS policy: {}, cipher suites: {:?}",
+        tls_settings.policy, tls_settings.cipher_suites
+    );
+    let tls_config = tls::server_config(&cert, &key, &client_ca_cert, &tls_settings)?;
 
-    let addr = "127.0.0.1:50051".parse()?;
-    let greeter = WorkFlowService::default();
+    let addr = config.listen_addr()?;
+    let store: Arc<dyn JobStore> = match &config.data_dir {
+        Some(dir) => Arc::new(DiskStore::open(dir)?),
+        None => Arc::new(MemoryStore::new()),
+    };
+    let worker = Worker::new()
+        .with_retry_policy(config.retry_policy()?)
+        .with_scheduler(config.scheduler()?)
+        .with_store(store)
+        .with_output_policy(config.output_policy());
+    worker.recover()?;
+    let greeter = WorkFlowService::new(worker)
+        .with_isolation(config.isolation()?)
+        .with_user_mapping(config.run_as.clone());
 
+    let listener = TcpListener::bind(addr).await?;
     info!("WorkFlowServer listening on {}", addr);
 
     Server::builder()
-        .tls_config(tls_config)?
-        .add_service(WorkFlowServer::new(greeter))
-        .serve(addr)
+        .add_service(WorkFlowServer::with_interceptor(
+            greeter,
+            identity::authenticate,
+        ))
+        .serve_with_incoming(tls::incoming(listener, tls_config))
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config file holding `json`, removed on drop.
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(json: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("server-test-{:016x}.json", rand::random::<u64>()));
+            std::fs::write(&path, json).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn merged(args: &[&str]) -> Result<ServerConfig> {
+        Cli::try_parse_from(["server"].iter().chain(args))?.into_config()
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let file = ConfigFile::new(
+            r#"{ "listen_addr": "127.0.0.1:50052", "max_running_jobs": 8, "cipher_suites": ["TLS13_AES_128_GCM_SHA256"] }"#,
+        );
+        let path = file.0.to_str().unwrap();
+
+        let config = merged(&["--config", path]).unwrap();
+        assert_eq!(config.listen_addr, "127.0.0.1:50052");
+        assert_eq!(config.max_running_jobs, 8);
+        assert_eq!(config.cipher_suites, ["TLS13_AES_128_GCM_SHA256"]);
+
+        let config = merged(&[
+            "--config",
+            path,
+            "--listen-addr",
+            "127.0.0.1:50053",
+            "--cipher-suites",
+            "TLS13_AES_256_GCM_SHA384,TLS13_CHACHA20_POLY1305_SHA256",
+        ])
+        .unwrap();
+        assert_eq!(config.listen_addr, "127.0.0.1:50053");
+        assert_eq!(config.max_running_jobs, 8);
+        assert_eq!(
+            config.cipher_suites,
+            ["TLS13_AES_256_GCM_SHA384", "TLS13_CHACHA20_POLY1305_SHA256"]
+        );
+    }
+
+    #[test]
+    fn environment_variables_override_the_file_and_flags_override_both() {
+        // The only test reading this variable, so setting it does not race
+        // with the others.
+        std::env::set_var("WORKFLOW_PRIORITY_AGING_SECS", "5");
+        let file = ConfigFile::new(r#"{ "priority_aging_secs": 60 }"#);
+        let path = file.0.to_str().unwrap();
+
+        let config = merged(&["--config", path]).unwrap();
+        assert_eq!(config.priority_aging_secs, 5);
+        let config = merged(&["--config", path, "--priority-aging-secs", "0"]).unwrap();
+        assert_eq!(config.priority_aging_secs, 0);
+        std::env::remove_var("WORKFLOW_PRIORITY_AGING_SECS");
+    }
+
+    #[test]
+    fn the_merged_config_is_validated() {
+        let file = ConfigFile::new(r#"{ "retry_initial_backoff_ms": 5000 }"#);
+        let path = file.0.to_str().unwrap();
+        merged(&["--config", path]).unwrap();
+        let err = merged(&["--config", path, "--retry-max-backoff-ms", "1000"]).unwrap_err();
+        assert!(err.to_string().contains("retry_max_backoff_ms"), "{}", err);
+
+        let file =
+            ConfigFile::new(r#"{ "run_as": { "roles": { "User": { "uid": 0, "gid": 0 } } } }"#);
+        let err = merged(&["--config", file.0.to_str().unwrap()]).unwrap_err();
+        assert!(err.to_string().contains("run_as"), "{}", err);
+    }
+}
@@ -0,0 +1,388 @@
+//! Linux cgroup v2 resource control.
+//!
+//! Every job runs in its own leaf cgroup below `easy_workflow` in the
+//! server's own cgroup, as read from `/proc/self/cgroup`, with `cpu.max`,
+//! `memory.max` and `io.max` derived from its [`Quota`]. The leaf is removed
+//! once the job has finished.
+//!
+//! Unless the server runs in the root cgroup, controllers can only be handed
+//! down from its cgroup once no process is left in it, so the server first
+//! moves itself into a `server` leaf next to `easy_workflow`. This works
+//! wherever the server's cgroup is delegated to it, as by systemd's
+//! `Delegate=yes`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use nix::libc;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+/// Name of the cgroup under which every job leaf is created.
+pub const PARENT_CGROUP: &str = "easy_workflow";
+/// Name of the leaf the server moves its own processes to.
+pub const SERVER_CGROUP: &str = "server";
+
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "io"];
+const CPU_PERIOD_US: u64 = 100_000;
+const MB: u64 = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum CgroupError {
+    #[error("cgroup v2 is not available on this host (no cgroup2 filesystem mounted)")]
+    Unavailable,
+    #[error("cgroup controller `{controller}` is not delegated to {path}")]
+    ControllerNotDelegated {
+        controller: &'static str,
+        path: PathBuf,
+    },
+    #[error("cgroup operation on {path} failed: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl CgroupError {
+    fn io(path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// Resource limits for a single job. A zero value leaves that resource unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    /// Number of CPU cores.
+    pub cpu: u32,
+    /// Memory limit in MB.
+    pub memory: u32,
+    /// Read and write bandwidth limit in MB/s, applied to every disk.
+    pub io: u32,
+}
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Quota::default()
+    }
+
+    fn cpu_max(&self) -> String {
+        match self.cpu {
+            0 => format!("max {}", CPU_PERIOD_US),
+            cpu => format!("{} {}", cpu as u64 * CPU_PERIOD_US, CPU_PERIOD_US),
+        }
+    }
+
+    fn memory_max(&self) -> String {
+        match self.memory {
+            0 => "max".to_string(),
+            memory => (memory as u64 * MB).to_string(),
+        }
+    }
+
+    fn io_max(&self, device: &str) -> String {
+        match self.io {
+            0 => format!("{} rbps=max wbps=max", device),
+            io => {
+                let bps = io as u64 * MB;
+                format!("{} rbps={} wbps={}", device, bps, bps)
+            }
+        }
+    }
+}
+
+/// Creates per-job cgroups below the `easy_workflow` parent cgroup.
+#[derive(Debug, Clone, Default)]
+pub struct CgroupManager {
+    /// Mount point of the cgroup2 hierarchy; discovered from `/proc/self/mounts` when unset.
+    mount: Option<PathBuf>,
+    // The parent once prepared, as the server's own cgroup changes when it
+    // moves out of the way.
+    parent: Arc<OnceLock<PathBuf>>,
+}
+
+impl CgroupManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the cgroup2 hierarchy mounted at `mount` instead of discovering it.
+    pub fn with_mount(mount: impl Into<PathBuf>) -> Self {
+        Self {
+            mount: Some(mount.into()),
+            parent: Arc::default(),
+        }
+    }
+
+    /// Create the leaf cgroup for `job_id` and apply `quota` to it.
+    pub fn create(&self, job_id: &str, quota: &Quota) -> Result<JobCgroup, CgroupError> {
+        let parent = self.prepare_parent()?;
+        let path = parent.join(job_id);
+        fs::create_dir(&path).map_err(|e| CgroupError::io(&path, e))?;
+
+        if let Err(e) = apply_quota(&path, quota) {
+            let _ = fs::remove_dir(&path);
+            return Err(e);
+        }
+
+        let procs_path = path.join("cgroup.procs");
+        let procs = match OpenOptions::new().write(true).open(&procs_path) {
+            Ok(procs) => procs,
+            Err(e) => {
+                let _ = fs::remove_dir(&path);
+                return Err(CgroupError::io(&procs_path, e));
+            }
+        };
+        debug!(cgroup = ?path, ?quota, "created job cgroup");
+        Ok(JobCgroup { path, procs })
+    }
+
+    /// Check that job cgroups can be created, so that jobs with a quota can
+    /// be refused up front rather than failing once they start.
+    pub fn check_available(&self) -> Result<(), CgroupError> {
+        self.prepare_parent().map(drop)
+    }
+
+    /// Make sure the parent cgroup exists and hands the cpu, memory and io
+    /// controllers down to its children.
+    fn prepare_parent(&self) -> Result<PathBuf, CgroupError> {
+        if let Some(parent) = self.parent.get() {
+            return Ok(parent.clone());
+        }
+        let mount = match &self.mount {
+            Some(mount) => mount.clone(),
+            None => find_cgroup2_mount()?,
+        };
+        if !mount.join("cgroup.controllers").is_file() {
+            return Err(CgroupError::Unavailable);
+        }
+
+        let own_path = Path::new("/proc/self/cgroup");
+        let own = fs::read_to_string(own_path).map_err(|e| CgroupError::io(own_path, e))?;
+        let own = parse_own_cgroup(&own).ok_or(CgroupError::Unavailable)?;
+        let base = mount.join(own.trim_start_matches('/'));
+        if base != mount {
+            move_processes(&base, &base.join(SERVER_CGROUP))?;
+        }
+        let parent = base.join(PARENT_CGROUP);
+        enable_controllers(&base)?;
+        if !parent.is_dir() {
+            fs::create_dir(&parent).map_err(|e| CgroupError::io(&parent, e))?;
+        }
+        enable_controllers(&parent)?;
+        debug!(cgroup = ?parent, "prepared parent cgroup");
+        Ok(self.parent.get_or_init(|| parent).clone())
+    }
+}
+
+/// A job's leaf cgroup. Dropping it does not remove the cgroup; call [`JobCgroup::remove`].
+#[derive(Debug)]
+pub struct JobCgroup {
+    path: PathBuf,
+    procs: File,
+}
+
+impl JobCgroup {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The leaf's `cgroup.procs`, opened for writing. Writing `0` to it moves
+    /// the calling process into the cgroup, which is how a freshly forked job
+    /// joins it before exec.
+    pub fn procs(&self) -> &File {
+        &self.procs
+    }
+
+    /// Kill anything left in the cgroup and remove it.
+    pub async fn remove(self) {
+        let JobCgroup { path, procs } = self;
+        drop(procs);
+        remove_leaf(&path).await;
+    }
+}
+
+/// Kill anything left in the job cgroup at `path`, which may have been left
+/// behind by a previous server, and remove it.
+pub async fn remove_leaf(path: &Path) {
+    let kill = path.join("cgroup.kill");
+    if kill.exists() {
+        if let Err(e) = fs::write(&kill, "1") {
+            warn!(cgroup = ?path, "failed to kill cgroup members: {}", e);
+        }
+    }
+    // The kernel needs a moment to reap killed members before the cgroup
+    // can be removed.
+    for _ in 0..10 {
+        match fs::remove_dir(path) {
+            Ok(()) => return,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    }
+    warn!(cgroup = ?path, "failed to remove job cgroup");
+}
+
+fn apply_quota(path: &Path, quota: &Quota) -> Result<(), CgroupError> {
+    write_file(&path.join("cpu.max"), &quota.cpu_max())?;
+    write_file(&path.join("memory.max"), &quota.memory_max())?;
+    let swap_max = path.join("memory.swap.max");
+    if quota.memory != 0 && swap_max.exists() {
+        write_file(&swap_max, "0")?;
+    }
+    for device in block_devices()? {
+        write_file(&path.join("io.max"), &quota.io_max(&device))?;
+    }
+    Ok(())
+}
+
+/// Path of the cgroup2 hierarchy entry of a `/proc/<pid>/cgroup` file,
+/// relative to the mount point.
+fn parse_own_cgroup(content: &str) -> Option<&str> {
+    content.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// Move every process of the cgroup at `from` into the leaf at `to`,
+/// creating it if needed.
+fn move_processes(from: &Path, to: &Path) -> Result<(), CgroupError> {
+    let pids = read_file(&from.join("cgroup.procs"))?;
+    if pids.trim().is_empty() {
+        return Ok(());
+    }
+    match fs::create_dir(to) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(CgroupError::io(to, e)),
+        _ => {}
+    }
+    let procs = to.join("cgroup.procs");
+    for pid in pids.lines() {
+        match fs::write(&procs, pid) {
+            // The process exited in the meantime.
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+            result => result.map_err(|e| CgroupError::io(&procs, e))?,
+        }
+    }
+    debug!(from = ?from, to = ?to, "moved server processes out of the way");
+    Ok(())
+}
+
+fn find_cgroup2_mount() -> Result<PathBuf, CgroupError> {
+    let mounts_path = Path::new("/proc/self/mounts");
+    let mounts = fs::read_to_string(mounts_path).map_err(|e| CgroupError::io(mounts_path, e))?;
+    mounts
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[2] == "cgroup2")
+        .map(|fields| PathBuf::from(fields[1]))
+        .ok_or(CgroupError::Unavailable)
+}
+
+fn enable_controllers(cgroup: &Path) -> Result<(), CgroupError> {
+    let available = read_file(&cgroup.join("cgroup.controllers"))?;
+    let subtree_control = cgroup.join("cgroup.subtree_control");
+    let enabled = read_file(&subtree_control)?;
+    for controller in CONTROLLERS {
+        if !available.split_whitespace().any(|c| c == controller) {
+            return Err(CgroupError::ControllerNotDelegated {
+                controller,
+                path: cgroup.to_path_buf(),
+            });
+        }
+        if !enabled.split_whitespace().any(|c| c == controller) {
+            fs::write(&subtree_control, format!("+{}", controller)).map_err(|_| {
+                CgroupError::ControllerNotDelegated {
+                    controller,
+                    path: cgroup.to_path_buf(),
+                }
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// `MAJ:MIN` of every whole disk on the host, skipping loop and ram devices.
+fn block_devices() -> Result<Vec<String>, CgroupError> {
+    let sys_block = Path::new("/sys/block");
+    let entries = fs::read_dir(sys_block).map_err(|e| CgroupError::io(sys_block, e))?;
+    let mut devices = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if ["loop", "ram", "zram"].iter().any(|p| name.starts_with(p)) {
+            continue;
+        }
+        devices.push(read_file(&entry.path().join("dev"))?.trim().to_string());
+    }
+    Ok(devices)
+}
+
+fn read_file(path: &Path) -> Result<String, CgroupError> {
+    fs::read_to_string(path).map_err(|e| CgroupError::io(path, e))
+}
+
+fn write_file(path: &Path, value: &str) -> Result<(), CgroupError> {
+    fs::write(path, value).map_err(|e| CgroupError::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_quotas_write_max() {
+        let quota = Quota::default();
+        assert!(quota.is_unlimited());
+        assert_eq!(quota.cpu_max(), "max 100000");
+        assert_eq!(quota.memory_max(), "max");
+        assert_eq!(quota.io_max("8:0"), "8:0 rbps=max wbps=max");
+    }
+
+    #[test]
+    fn cpu_cores_become_a_share_of_the_period() {
+        let quota = Quota {
+            cpu: 2,
+            ..Quota::default()
+        };
+        assert!(!quota.is_unlimited());
+        assert_eq!(quota.cpu_max(), "200000 100000");
+        // Only CPU is limited.
+        assert_eq!(quota.memory_max(), "max");
+    }
+
+    #[test]
+    fn memory_is_given_in_megabytes() {
+        let quota = Quota {
+            memory: 512,
+            ..Quota::default()
+        };
+        assert_eq!(quota.memory_max(), (512 * 1024 * 1024).to_string());
+    }
+
+    #[test]
+    fn io_bandwidth_limits_reads_and_writes_of_the_device() {
+        let quota = Quota {
+            io: 10,
+            ..Quota::default()
+        };
+        let bps = 10 * 1024 * 1024;
+        assert_eq!(
+            quota.io_max("259:0"),
+            format!("259:0 rbps={} wbps={}", bps, bps)
+        );
+    }
+
+    #[test]
+    fn finds_the_unified_hierarchy_of_a_process() {
+        let hybrid =
+            "12:memory:/user.slice\n1:name=systemd:/user.slice/x.scope\n0::/user.slice/x.scope\n";
+        assert_eq!(parse_own_cgroup(hybrid), Some("/user.slice/x.scope"));
+        assert_eq!(parse_own_cgroup("0::/\n"), Some("/"));
+        assert_eq!(parse_own_cgroup("4:memory:/job\n"), None);
+    }
+}
@@ -0,0 +1,457 @@
+//! Server configuration.
+//!
+//! The server reads an optional JSON file (see `server_config.json`), whose
+//! missing fields fall back to the defaults below. Command line flags and
+//! environment variables are applied on top by the server binary, and the
+//! result is checked by [`ServerConfig::validate`].
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use nix::unistd::geteuid;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::cgroup::Quota;
+use crate::isolation::Isolation;
+use crate::output::OutputPolicy;
+use crate::retry::RetryPolicy;
+use crate::scheduler::{Scheduler, DEFAULT_AGING, DEFAULT_SLOTS};
+use crate::tls::{TlsPolicy, TlsSettings};
+use crate::users::UserMapping;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read config file {path:?}: {source}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("failed to parse config file {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("invalid `{field}`: {reason}")]
+    InvalidField { field: &'static str, reason: String },
+}
+
+impl ConfigError {
+    fn invalid(field: &'static str, reason: impl ToString) -> Self {
+        Self::InvalidField {
+            field,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// CA certificate used to verify client certificates.
+    pub ca_crt: PathBuf,
+    /// Server certificate.
+    pub crt: PathBuf,
+    /// Server private key.
+    pub key: PathBuf,
+    /// Address the gRPC server listens on.
+    pub listen_addr: String,
+    /// Address of the Prometheus `/metrics` listener.
+    pub metrics_addr: String,
+    /// Value of the global `service` label on every metric.
+    pub service_label: String,
+    /// `tls13` or `modern`.
+    pub tls_policy: String,
+    /// Optional cipher suite allow-list narrowing `tls_policy`.
+    pub cipher_suites: Vec<String>,
+    /// Delay before the first retry of a failed job, in milliseconds.
+    pub retry_initial_backoff_ms: u64,
+    /// Upper bound of the retry delay, in milliseconds.
+    pub retry_max_backoff_ms: u64,
+    /// Factor applied to the retry delay after every retry.
+    pub retry_backoff_multiplier: f64,
+    /// Relative random jitter of the retry delay, between 0 and 1.
+    pub retry_jitter: f64,
+    /// Number of jobs allowed to run at the same time.
+    pub max_running_jobs: usize,
+    /// Seconds a queued job waits for each priority level it gains; 0
+    /// disables aging.
+    pub priority_aging_secs: u64,
+    /// CPU cores that the quotas of running jobs may add up to; 0 leaves
+    /// CPU unaccounted.
+    pub capacity_cpu: u32,
+    /// Memory in MB that the quotas of running jobs may add up to; 0 leaves
+    /// memory unaccounted.
+    pub capacity_memory: u32,
+    /// IO bandwidth in MB/s that the quotas of running jobs may add up to;
+    /// 0 leaves IO unaccounted.
+    pub capacity_io: u32,
+    /// Directory of the persistent job store; when unset, jobs are only kept
+    /// in memory and their output in unlinked files of the temporary
+    /// directory.
+    pub data_dir: Option<PathBuf>,
+    /// Size in bytes at which the output of a job attempt is truncated; 0
+    /// keeps everything.
+    pub max_output_bytes: u64,
+    /// Seconds the output of a finished job is kept; 0 keeps it forever.
+    pub output_retention_secs: u64,
+    /// Number of finished jobs that keep their output, most recently
+    /// finished first; 0 keeps every job's.
+    pub max_retained_outputs: usize,
+    /// Isolation of jobs that do not ask for one: `none` or `namespaces`.
+    /// Only admins may ask for less.
+    pub isolation: String,
+    /// Unix accounts the jobs of each client run as; only settable in the
+    /// config file, and only honoured by a server running as root. Nobody
+    /// may be mapped to the root user or group.
+    pub run_as: UserMapping,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let retry = RetryPolicy::default();
+        let output = OutputPolicy::default();
+        Self {
+            ca_crt: PathBuf::from("certs/ca.crt"),
+            crt: PathBuf::from("certs/server.crt"),
+            key: PathBuf::from("certs/server.key"),
+            listen_addr: "127.0.0.1:50051".to_string(),
+            metrics_addr: "127.0.0.1:9091".to_string(),
+            service_label: "my_awesome_service".to_string(),
+            tls_policy: TlsPolicy::default().to_string(),
+            cipher_suites: Vec::new(),
+            retry_initial_backoff_ms: retry.initial_backoff.as_millis() as u64,
+            retry_max_backoff_ms: retry.max_backoff.as_millis() as u64,
+            retry_backoff_multiplier: retry.multiplier,
+            retry_jitter: retry.jitter,
+            max_running_jobs: DEFAULT_SLOTS,
+            priority_aging_secs: DEFAULT_AGING.as_secs(),
+            capacity_cpu: 0,
+            capacity_memory: 0,
+            capacity_io: 0,
+            data_dir: None,
+            max_output_bytes: output.max_size.unwrap_or_default(),
+            output_retention_secs: output.max_age.unwrap_or_default().as_secs(),
+            max_retained_outputs: output.max_jobs.unwrap_or_default(),
+            isolation: Isolation::default().to_string(),
+            run_as: UserMapping::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load the configuration from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        serde_json::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Check every field, reporting the first invalid one by name.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (field, path) in [
+            ("ca_crt", &self.ca_crt),
+            ("crt", &self.crt),
+            ("key", &self.key),
+        ] {
+            if !path.is_file() {
+                return Err(ConfigError::invalid(
+                    field,
+                    format!("{:?} does not exist", path),
+                ));
+            }
+        }
+        self.listen_addr()?;
+        self.metrics_addr()?;
+        if self.service_label.trim().is_empty() {
+            return Err(ConfigError::invalid("service_label", "must not be empty"));
+        }
+        self.tls_settings()?;
+        self.retry_policy()?;
+        self.scheduler()?;
+        self.isolation()?;
+        self.validate_run_as()?;
+        if let Some(dir) = &self.data_dir {
+            if dir.exists() && !dir.is_dir() {
+                return Err(ConfigError::invalid(
+                    "data_dir",
+                    format!("{:?} is not a directory", dir),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_run_as(&self) -> Result<(), ConfigError> {
+        let cns = self
+            .run_as
+            .cns
+            .iter()
+            .map(|(cn, credentials)| (format!("CN {}", cn), credentials));
+        let roles = self
+            .run_as
+            .roles
+            .iter()
+            .map(|(role, credentials)| (format!("role {}", role), credentials));
+        for (client, credentials) in cns.chain(roles) {
+            if credentials.uid == 0 || credentials.gid == 0 {
+                return Err(ConfigError::invalid(
+                    "run_as",
+                    format!("{} is mapped to the root user or group", client),
+                ));
+            }
+        }
+        if !self.run_as.is_empty() && !geteuid().is_root() {
+            return Err(ConfigError::invalid(
+                "run_as",
+                "switching users needs a server running as root",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn listen_addr(&self) -> Result<SocketAddr, ConfigError> {
+        self.listen_addr
+            .parse()
+            .map_err(|e| ConfigError::invalid("listen_addr", e))
+    }
+
+    pub fn metrics_addr(&self) -> Result<SocketAddr, ConfigError> {
+        self.metrics_addr
+            .parse()
+            .map_err(|e| ConfigError::invalid("metrics_addr", e))
+    }
+
+    pub fn tls_settings(&self) -> Result<TlsSettings, ConfigError> {
+        let settings = TlsSettings {
+            policy: self
+                .tls_policy
+                .parse()
+                .map_err(|e| ConfigError::invalid("tls_policy", e))?,
+            cipher_suites: self.cipher_suites.clone(),
+        };
+        settings
+            .selected_cipher_suites()
+            .map_err(|e| ConfigError::invalid("cipher_suites", e))?;
+        Ok(settings)
+    }
+
+    pub fn retry_policy(&self) -> Result<RetryPolicy, ConfigError> {
+        if self.retry_max_backoff_ms < self.retry_initial_backoff_ms {
+            return Err(ConfigError::invalid(
+                "retry_max_backoff_ms",
+                "must not be less than retry_initial_backoff_ms",
+            ));
+        }
+        let multiplier = self.retry_backoff_multiplier;
+        if !multiplier.is_finite() || multiplier < 1.0 {
+            return Err(ConfigError::invalid(
+                "retry_backoff_multiplier",
+                "must be a finite number of at least 1",
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.retry_jitter) {
+            return Err(ConfigError::invalid(
+                "retry_jitter",
+                "must be between 0 and 1",
+            ));
+        }
+        Ok(RetryPolicy {
+            initial_backoff: Duration::from_millis(self.retry_initial_backoff_ms),
+            max_backoff: Duration::from_millis(self.retry_max_backoff_ms),
+            multiplier: self.retry_backoff_multiplier,
+            jitter: self.retry_jitter,
+        })
+    }
+
+    pub fn scheduler(&self) -> Result<Scheduler, ConfigError> {
+        if self.max_running_jobs == 0 {
+            return Err(ConfigError::invalid(
+                "max_running_jobs",
+                "must be at least 1",
+            ));
+        }
+        let aging = match self.priority_aging_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        let capacity = Quota {
+            cpu: self.capacity_cpu,
+            memory: self.capacity_memory,
+            io: self.capacity_io,
+        };
+        Ok(Scheduler::new(self.max_running_jobs, aging).with_capacity(capacity))
+    }
+
+    pub fn isolation(&self) -> Result<Isolation, ConfigError> {
+        self.isolation
+            .parse()
+            .map_err(|e| ConfigError::invalid("isolation", e))
+    }
+
+    pub fn output_policy(&self) -> OutputPolicy {
+        OutputPolicy {
+            max_size: Some(self.max_output_bytes).filter(|&size| size != 0),
+            max_age: Some(self.output_retention_secs)
+                .filter(|&secs| secs != 0)
+                .map(Duration::from_secs),
+            max_jobs: Some(self.max_retained_outputs).filter(|&jobs| jobs != 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::auth::Role;
+    use crate::users::Credentials;
+
+    /// Load `json` as a config file.
+    fn load(json: &str) -> Result<ServerConfig, ConfigError> {
+        let path =
+            std::env::temp_dir().join(format!("config-test-{:016x}.json", rand::random::<u64>()));
+        std::fs::write(&path, json).unwrap();
+        let config = ServerConfig::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn invalid_field(err: ConfigError) -> (&'static str, String) {
+        match err {
+            ConfigError::InvalidField { field, reason } => (field, reason),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    fn credentials(uid: u32, gid: u32) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            groups: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn the_shipped_config_is_valid() {
+        let config = ServerConfig::from_file(Path::new("server_config.json")).unwrap();
+        config.validate().unwrap();
+        assert!(config.run_as.is_empty());
+    }
+
+    #[test]
+    fn missing_fields_take_their_defaults() {
+        let config = load(r#"{ "listen_addr": "0.0.0.0:50052", "max_running_jobs": 8 }"#).unwrap();
+        assert_eq!(config.listen_addr, "0.0.0.0:50052");
+        assert_eq!(config.max_running_jobs, 8);
+
+        let defaults = ServerConfig::default();
+        assert_eq!(config.metrics_addr, defaults.metrics_addr);
+        assert_eq!(config.tls_policy, defaults.tls_policy);
+        assert_eq!(
+            config.retry_policy().unwrap(),
+            defaults.retry_policy().unwrap()
+        );
+        assert_eq!(config.run_as, defaults.run_as);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        for json in [
+            r#"{ "listen_adr": "0.0.0.0:50052" }"#,
+            r#"{ "run_as": { "users": {} } }"#,
+            r#"{ "run_as": { "cns": { "Alice": { "uid": 1000, "gid": 1000, "home": "/" } } } }"#,
+        ] {
+            let err = load(json).unwrap_err();
+            assert!(
+                matches!(&err, ConfigError::Parse { source, .. } if source.to_string().contains("unknown field")),
+                "{}: {}",
+                json,
+                err
+            );
+        }
+        let err = ServerConfig::from_file(Path::new("does-not-exist.json")).unwrap_err();
+        assert!(matches!(err, ConfigError::Read { .. }), "{}", err);
+    }
+
+    #[test]
+    fn invalid_fields_are_reported_by_name() {
+        for (field, config) in [
+            (
+                "listen_addr",
+                ServerConfig {
+                    listen_addr: "localhost".to_string(),
+                    ..ServerConfig::default()
+                },
+            ),
+            (
+                "retry_max_backoff_ms",
+                ServerConfig {
+                    retry_max_backoff_ms: 10,
+                    retry_initial_backoff_ms: 20,
+                    ..ServerConfig::default()
+                },
+            ),
+            (
+                "max_running_jobs",
+                ServerConfig {
+                    max_running_jobs: 0,
+                    ..ServerConfig::default()
+                },
+            ),
+            (
+                "isolation",
+                ServerConfig {
+                    isolation: "chroot".to_string(),
+                    ..ServerConfig::default()
+                },
+            ),
+        ] {
+            assert_eq!(invalid_field(config.validate().unwrap_err()).0, field);
+        }
+    }
+
+    #[test]
+    fn nobody_may_be_mapped_to_root() {
+        for root in [credentials(0, 1000), credentials(1000, 0)] {
+            let mut config = ServerConfig::default();
+            config.run_as.cns.insert("Alice".to_string(), root.clone());
+            let (field, reason) = invalid_field(config.validate().unwrap_err());
+            assert_eq!(field, "run_as");
+            assert_eq!(reason, "CN Alice is mapped to the root user or group");
+
+            let mut config = ServerConfig::default();
+            config.run_as.roles = HashMap::from([(Role::Admin, root)]);
+            let (field, reason) = invalid_field(config.validate().unwrap_err());
+            assert_eq!(field, "run_as");
+            assert_eq!(reason, "role Admin is mapped to the root user or group");
+        }
+    }
+
+    #[test]
+    fn switching_users_needs_a_root_server() {
+        let mut config = ServerConfig::default();
+        config
+            .run_as
+            .roles
+            .insert(Role::User, credentials(1000, 1000));
+        let result = config.validate();
+        if geteuid().is_root() {
+            result.unwrap();
+        } else {
+            let (field, reason) = invalid_field(result.unwrap_err());
+            assert_eq!(field, "run_as");
+            assert_eq!(reason, "switching users needs a server running as root");
+        }
+    }
+}
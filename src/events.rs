@@ -0,0 +1,326 @@
+//! Job state-change feed.
+//!
+//! Every state a job enters is published to the worker's [`EventLog`] under
+//! a sequence number. The log keeps the most recent [`HISTORY_SIZE`] events
+//! so that an [`EventReader`] can resume right after the last event it saw,
+//! as long as that event is still in the history. Sequence numbers restart
+//! with the server, so resume tokens also carry a random epoch drawn once per
+//! process, and tokens from an earlier run are refused.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use thiserror::Error;
+
+use crate::notify::{Listener, Notifier};
+use crate::state::{JobState, JobStatus};
+
+/// Number of past events kept for resuming readers.
+pub const HISTORY_SIZE: usize = 10_000;
+
+#[derive(Debug, Error)]
+pub enum EventError {
+    #[error("events after {0} are no longer available")]
+    Expired(ResumeToken),
+    #[error("resume token {0} was issued before the server restarted")]
+    Restarted(ResumeToken),
+    #[error("unknown resume token {0}")]
+    Unknown(ResumeToken),
+}
+
+#[derive(Debug, Error)]
+#[error("invalid resume token `{0}`")]
+pub struct InvalidResumeToken(String);
+
+/// Position of an event in the log of one server process, formatted as
+/// `<epoch>-<seq>` with the epoch in hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeToken {
+    epoch: u64,
+    seq: u64,
+}
+
+impl fmt::Display for ResumeToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}-{}", self.epoch, self.seq)
+    }
+}
+
+impl FromStr for ResumeToken {
+    type Err = InvalidResumeToken;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidResumeToken(s.to_string());
+        let (epoch, seq) = s.split_once('-').ok_or_else(invalid)?;
+        Ok(Self {
+            epoch: u64::from_str_radix(epoch, 16).map_err(|_| invalid())?,
+            seq: seq.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A job entering a state.
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    /// Where to resume right after this event.
+    pub token: ResumeToken,
+    pub job_id: String,
+    pub owner: String,
+    pub labels: BTreeMap<String, String>,
+    pub state: JobState,
+    pub attempt: u32,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub at: SystemTime,
+}
+
+#[derive(Debug)]
+struct History {
+    events: VecDeque<Arc<StateChange>>,
+    next_seq: u64,
+}
+
+impl History {
+    fn first_seq(&self) -> u64 {
+        self.events
+            .front()
+            .map_or(self.next_seq, |event| event.token.seq)
+    }
+}
+
+#[derive(Debug)]
+pub struct EventLog {
+    epoch: u64,
+    history: Mutex<History>,
+    // Notified on every publish.
+    changed: Notifier,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self {
+            epoch: rand::random(),
+            history: Mutex::new(History {
+                events: VecDeque::new(),
+                next_seq: 1,
+            }),
+            changed: Notifier::new(),
+        }
+    }
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that job `job_id` of `owner` entered `status.state`.
+    pub fn publish(
+        &self,
+        job_id: &str,
+        owner: &str,
+        labels: &BTreeMap<String, String>,
+        status: &JobStatus,
+    ) {
+        {
+            let mut history = self.history.lock().unwrap();
+            let seq = history.next_seq;
+            history.next_seq += 1;
+            history.events.push_back(Arc::new(StateChange {
+                token: ResumeToken {
+                    epoch: self.epoch,
+                    seq,
+                },
+                job_id: job_id.to_string(),
+                owner: owner.to_string(),
+                labels: labels.clone(),
+                state: status.state,
+                attempt: status.attempt,
+                exit_code: status.exit_code,
+                signal: status.signal,
+                at: SystemTime::now(),
+            }));
+            if history.events.len() > HISTORY_SIZE {
+                history.events.pop_front();
+            }
+        }
+        self.changed.notify();
+    }
+
+    /// Follow the log from the event after `after`, or from the next event
+    /// to be published.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        after: Option<ResumeToken>,
+    ) -> Result<EventReader, EventError> {
+        let history = self.history.lock().unwrap();
+        let next = match after {
+            None => history.next_seq,
+            Some(after) if after.epoch != self.epoch => return Err(EventError::Restarted(after)),
+            Some(after) if after.seq >= history.next_seq => return Err(EventError::Unknown(after)),
+            Some(after) if after.seq + 1 < history.first_seq() => {
+                return Err(EventError::Expired(after))
+            }
+            Some(after) => after.seq + 1,
+        };
+        Ok(EventReader {
+            log: self.clone(),
+            next,
+            changed: self.changed.subscribe(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct EventReader {
+    log: Arc<EventLog>,
+    next: u64,
+    changed: Listener,
+}
+
+impl EventReader {
+    /// Return the next event, waiting for one to be published if the reader
+    /// has caught up. Fails if the reader fell so far behind that the event
+    /// has left the history.
+    pub async fn next(&mut self) -> Result<Arc<StateChange>, EventError> {
+        let (log, next) = (&self.log, &mut self.next);
+        let event = self.changed.wait_for(|| {
+            let history = log.history.lock().unwrap();
+            let first = history.first_seq();
+            if *next < first {
+                return Some(Err(EventError::Expired(ResumeToken {
+                    epoch: log.epoch,
+                    seq: *next - 1,
+                })));
+            }
+            let event = history.events.get((*next - first) as usize)?.clone();
+            *next += 1;
+            Some(Ok(event))
+        });
+        // The log lives as long as the reader holds it, so the notifier
+        // cannot go away.
+        event.await.expect("event log notifier dropped")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn publish(log: &EventLog, job_id: &str) {
+        log.publish(job_id, "Alice", &BTreeMap::new(), &JobStatus::new());
+    }
+
+    /// A log holding an event of job `job-<n>` for every `n` in `0..count`.
+    fn log_with(count: usize) -> Arc<EventLog> {
+        let log = Arc::new(EventLog::new());
+        for i in 0..count {
+            publish(&log, &format!("job-{}", i));
+        }
+        log
+    }
+
+    fn token(log: &EventLog, seq: u64) -> ResumeToken {
+        ResumeToken {
+            epoch: log.epoch,
+            seq,
+        }
+    }
+
+    #[tokio::test]
+    async fn readers_resume_right_after_their_token() {
+        let log = log_with(3);
+        let mut reader = log.subscribe(None).unwrap();
+        publish(&log, "job-3");
+        let first = reader.next().await.unwrap();
+        assert_eq!(first.job_id, "job-3");
+
+        let mut reader = log.subscribe(Some(token(&log, 2))).unwrap();
+        for expected in ["job-2", "job-3"] {
+            let event = reader.next().await.unwrap();
+            assert_eq!(event.job_id, expected);
+        }
+        // Resuming from the last token waits for the next event.
+        let mut reader = log.subscribe(Some(first.token)).unwrap();
+        publish(&log, "job-4");
+        assert_eq!(reader.next().await.unwrap().job_id, "job-4");
+    }
+
+    #[tokio::test]
+    async fn readers_wait_for_new_events() {
+        let log = log_with(0);
+        let mut reader = log.subscribe(None).unwrap();
+        let next = tokio::spawn(async move { reader.next().await.unwrap() });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        publish(&log, "job-0");
+        let event = next.await.unwrap();
+        assert_eq!(event.job_id, "job-0");
+        assert_eq!(event.token, token(&log, 1));
+    }
+
+    #[tokio::test]
+    async fn tokens_expire_once_the_history_wraps() {
+        let log = log_with(HISTORY_SIZE + 1);
+        // The first event left the history, so the reader would miss it.
+        let err = log.subscribe(Some(token(&log, 0))).unwrap_err();
+        assert!(matches!(err, EventError::Expired(_)), "{}", err);
+        let mut reader = log.subscribe(Some(token(&log, 1))).unwrap();
+        assert_eq!(reader.next().await.unwrap().job_id, "job-1");
+    }
+
+    #[tokio::test]
+    async fn readers_that_fall_behind_the_history_expire() {
+        let log = log_with(1);
+        let mut reader = log.subscribe(Some(token(&log, 0))).unwrap();
+        for _ in 0..HISTORY_SIZE {
+            publish(&log, "job-1");
+        }
+        let err = reader.next().await.unwrap_err();
+        assert!(
+            matches!(err, EventError::Expired(token) if token.seq == 0),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn tokens_from_the_future_are_unknown() {
+        let log = log_with(2);
+        let err = log.subscribe(Some(token(&log, 3))).unwrap_err();
+        assert!(matches!(err, EventError::Unknown(_)), "{}", err);
+        assert!(log.subscribe(Some(token(&log, 2))).is_ok());
+    }
+
+    #[test]
+    fn tokens_of_another_server_run_are_refused() {
+        let log = log_with(2);
+        let earlier = EventLog::new();
+        let err = log.subscribe(Some(token(&earlier, 1))).unwrap_err();
+        assert!(matches!(err, EventError::Restarted(_)), "{}", err);
+    }
+
+    #[test]
+    fn tokens_round_trip_through_their_text_form() {
+        let token = ResumeToken {
+            epoch: 0x00c0_ffee,
+            seq: 42,
+        };
+        assert_eq!(token.to_string(), "0000000000c0ffee-42");
+        assert_eq!(token.to_string().parse::<ResumeToken>().unwrap(), token);
+        let token = ResumeToken {
+            epoch: u64::MAX,
+            seq: u64::MAX,
+        };
+        assert_eq!(token.to_string().parse::<ResumeToken>().unwrap(), token);
+        for invalid in [
+            "", "42", "c0ffee", "c0ffee-", "-42", "xyz-42", "c0ffee-x", "1-2-3",
+        ] {
+            assert!(invalid.parse::<ResumeToken>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -25,31 +25,112 @@ pub struct EnvironmentVariables {
     #[prost(string, tag = "2")]
     pub value: ::prost::alloc::string::String,
 }
+/// What to run. Exactly one of cmd and argv must be set.
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Entrypoint {
+    /// Shell command line; shorthand for argv = \[cmd\] with shell set.
     #[prost(string, tag = "1")]
     pub cmd: ::prost::alloc::string::String,
     #[prost(message, repeated, tag = "2")]
     pub envs: ::prost::alloc::vec::Vec<EnvironmentVariables>,
+    /// Program and its arguments, run without a shell. A program without a
+    /// slash is looked up in PATH.
+    #[prost(string, repeated, tag = "3")]
+    pub argv: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// Run argv\[0\] as a command line with /bin/sh -c instead, the rest of argv
+    /// becoming its positional parameters $1, $2, ...
+    #[prost(bool, tag = "4")]
+    pub shell: bool,
+    /// Absolute directory to run in; empty means the server's.
+    #[prost(string, tag = "5")]
+    pub working_dir: ::prost::alloc::string::String,
+    /// Fed to the job's standard input, which is empty otherwise.
+    #[prost(bytes = "vec", optional, tag = "6")]
+    pub stdin: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    #[prost(enumeration = "EnvPolicy", tag = "7")]
+    pub env_policy: i32,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct JobStatusRequest {
+pub struct StartJobRequest {
     #[prost(message, optional, tag = "1")]
     pub entrypoint: ::core::option::Option<Entrypoint>,
     #[prost(message, optional, tag = "2")]
     pub quota: ::core::option::Option<Quota>,
+    /// Maximum run time in seconds; 0 means no timeout.
     #[prost(uint32, tag = "3")]
     pub timeout: u32,
+    /// Times to re-run the job after a failed or timed-out attempt.
     #[prost(uint32, tag = "4")]
     pub retry_count: u32,
+    /// -10 to 10; higher runs first.
     #[prost(int32, tag = "5")]
     pub priority: i32,
-    #[prost(string, repeated, tag = "6")]
-    pub labels: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
-    #[prost(string, repeated, tag = "7")]
-    pub annotations: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// Identifying key/value pairs, e.g. team=infra.
+    #[prost(map = "string, string", tag = "6")]
+    pub labels: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// Free-form key/value metadata.
+    #[prost(map = "string, string", tag = "7")]
+    pub annotations: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// Only admins may ask for less than the server's default.
+    #[prost(enumeration = "Isolation", tag = "8")]
+    pub isolation: i32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StartJobResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::core::option::Option<ResponseHeader>,
+    #[prost(string, tag = "2")]
+    pub job_id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StopJobRequest {
+    #[prost(string, tag = "1")]
+    pub job_id: ::prost::alloc::string::String,
+    /// Seconds between SIGTERM and SIGKILL; 0 uses the server default.
+    #[prost(uint32, tag = "2")]
+    pub grace_period_secs: u32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StopJobResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::core::option::Option<ResponseHeader>,
+    #[prost(enumeration = "StopOutcome", tag = "2")]
+    pub outcome: i32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JobStatusRequest {
+    #[prost(string, tag = "8")]
+    pub job_id: ::prost::alloc::string::String,
+}
+/// A finished attempt of a job.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Attempt {
+    #[prost(uint32, tag = "1")]
+    pub number: u32,
+    /// State the job entered when the attempt ended.
+    #[prost(enumeration = "JobState", tag = "2")]
+    pub state: i32,
+    #[prost(int32, optional, tag = "3")]
+    pub exit_code: ::core::option::Option<i32>,
+    #[prost(int32, optional, tag = "4")]
+    pub signal: ::core::option::Option<i32>,
+    #[prost(int64, tag = "5")]
+    pub started_at_ms: i64,
+    #[prost(int64, tag = "6")]
+    pub finished_at_ms: i64,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -58,6 +139,360 @@ pub struct JobStatusResponse {
     pub header: ::core::option::Option<ResponseHeader>,
     #[prost(string, tag = "2")]
     pub job_id: ::prost::alloc::string::String,
+    #[prost(enumeration = "JobState", tag = "3")]
+    pub state: i32,
+    /// Unset while running or when the process was killed by a signal.
+    #[prost(int32, optional, tag = "4")]
+    pub exit_code: ::core::option::Option<i32>,
+    /// Set when the job was ended by StopJob.
+    #[prost(enumeration = "StopOutcome", tag = "5")]
+    pub stop_outcome: i32,
+    /// Signal that terminated the last attempt.
+    #[prost(int32, optional, tag = "6")]
+    pub signal: ::core::option::Option<i32>,
+    /// Number of attempts started so far; 0 while queued.
+    #[prost(uint32, tag = "7")]
+    pub attempt: u32,
+    /// Timestamps in milliseconds since the Unix epoch. started_at_ms and
+    /// finished_at_ms refer to the last attempt.
+    #[prost(int64, tag = "8")]
+    pub created_at_ms: i64,
+    #[prost(int64, optional, tag = "9")]
+    pub started_at_ms: ::core::option::Option<i64>,
+    #[prost(int64, optional, tag = "10")]
+    pub finished_at_ms: ::core::option::Option<i64>,
+    /// Every finished attempt, oldest first.
+    #[prost(message, repeated, tag = "11")]
+    pub attempts: ::prost::alloc::vec::Vec<Attempt>,
+    /// Position among the jobs waiting to run, 1 being next; unset when the
+    /// job is not waiting.
+    #[prost(uint32, optional, tag = "12")]
+    pub queue_position: ::core::option::Option<u32>,
+    #[prost(map = "string, string", tag = "13")]
+    pub labels: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    #[prost(map = "string, string", tag = "14")]
+    pub annotations: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// Isolation the job runs with, after applying the server's default.
+    #[prost(enumeration = "Isolation", tag = "15")]
+    pub isolation: i32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StreamOutputRequest {
+    #[prost(string, tag = "1")]
+    pub job_id: ::prost::alloc::string::String,
+    /// Attempt whose output to stream, counting from 1; 0 means the latest.
+    #[prost(uint32, tag = "2")]
+    pub attempt: u32,
+    /// Only output of this stream; unspecified means both.
+    #[prost(enumeration = "OutputStream", tag = "3")]
+    pub stream: i32,
+    /// Offset in the attempt's output to start at, e.g. the end of the last
+    /// chunk received.
+    #[prost(uint64, tag = "4")]
+    pub offset: u64,
+    /// Start at the last tail_lines lines written so far instead of at offset;
+    /// 0 disables.
+    #[prost(uint32, tag = "5")]
+    pub tail_lines: u32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct OutputChunk {
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+    #[prost(enumeration = "OutputStream", tag = "2")]
+    pub stream: i32,
+    /// Offset of data in the attempt's output, counting both streams.
+    #[prost(uint64, tag = "3")]
+    pub offset: u64,
+    /// Milliseconds since the attempt started, from a monotonic clock.
+    #[prost(uint64, tag = "4")]
+    pub timestamp_ms: u64,
+    /// Attempt the output belongs to, counting from 1.
+    #[prost(uint32, tag = "5")]
+    pub attempt: u32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListJobsRequest {
+    /// Kubernetes-style equality-based selector, e.g. "team=infra,env!=prod".
+    #[prost(string, tag = "1")]
+    pub label_selector: ::prost::alloc::string::String,
+    /// Only jobs in any of these states; empty means every state.
+    #[prost(enumeration = "JobState", repeated, tag = "2")]
+    pub states: ::prost::alloc::vec::Vec<i32>,
+    /// Only jobs of this owner. Non-admins only ever see their own jobs.
+    #[prost(string, tag = "3")]
+    pub owner: ::prost::alloc::string::String,
+    /// next_page_token of the previous response; empty for the first page.
+    #[prost(string, tag = "4")]
+    pub page_token: ::prost::alloc::string::String,
+    /// 0 uses the server default.
+    #[prost(uint32, tag = "5")]
+    pub page_size: u32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JobSummary {
+    #[prost(string, tag = "1")]
+    pub job_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub owner: ::prost::alloc::string::String,
+    #[prost(enumeration = "JobState", tag = "3")]
+    pub state: i32,
+    #[prost(uint32, tag = "4")]
+    pub attempt: u32,
+    #[prost(int32, tag = "5")]
+    pub priority: i32,
+    #[prost(int64, tag = "6")]
+    pub created_at_ms: i64,
+    #[prost(map = "string, string", tag = "7")]
+    pub labels: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    #[prost(string, tag = "8")]
+    pub cmd: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListJobsResponse {
+    #[prost(message, optional, tag = "1")]
+    pub header: ::core::option::Option<ResponseHeader>,
+    /// Oldest first.
+    #[prost(message, repeated, tag = "2")]
+    pub jobs: ::prost::alloc::vec::Vec<JobSummary>,
+    /// Empty on the last page.
+    #[prost(string, tag = "3")]
+    pub next_page_token: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WatchJobsRequest {
+    /// Only jobs matching this selector; empty matches every job.
+    #[prost(string, tag = "1")]
+    pub label_selector: ::prost::alloc::string::String,
+    /// Only these jobs; empty matches every job.
+    #[prost(string, repeated, tag = "2")]
+    pub job_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// resume_token of the last change received, to continue right after it.
+    /// Empty starts with the next change. Tokens from before a server restart
+    /// fail with OUT_OF_RANGE.
+    #[prost(string, tag = "3")]
+    pub resume_token: ::prost::alloc::string::String,
+}
+/// A job entering a state. Non-admins only receive changes of their own jobs.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JobStateChange {
+    #[prost(string, tag = "1")]
+    pub resume_token: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub job_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub owner: ::prost::alloc::string::String,
+    #[prost(enumeration = "JobState", tag = "4")]
+    pub state: i32,
+    #[prost(uint32, tag = "5")]
+    pub attempt: u32,
+    #[prost(int32, optional, tag = "6")]
+    pub exit_code: ::core::option::Option<i32>,
+    #[prost(int32, optional, tag = "7")]
+    pub signal: ::core::option::Option<i32>,
+    #[prost(int64, tag = "8")]
+    pub timestamp_ms: i64,
+    #[prost(map = "string, string", tag = "9")]
+    pub labels: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum EnvPolicy {
+    /// Same as ENV_POLICY_INHERIT.
+    Unspecified = 0,
+    /// Start from the server's environment.
+    Inherit = 1,
+    /// Start from an empty environment; only envs are set.
+    Clear = 2,
+}
+impl EnvPolicy {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            EnvPolicy::Unspecified => "ENV_POLICY_UNSPECIFIED",
+            EnvPolicy::Inherit => "ENV_POLICY_INHERIT",
+            EnvPolicy::Clear => "ENV_POLICY_CLEAR",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "ENV_POLICY_UNSPECIFIED" => Some(Self::Unspecified),
+            "ENV_POLICY_INHERIT" => Some(Self::Inherit),
+            "ENV_POLICY_CLEAR" => Some(Self::Clear),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Isolation {
+    /// The server's default.
+    Unspecified = 0,
+    /// Share the server's namespaces.
+    None = 1,
+    /// New PID, mount, network and UTS namespaces, with a private /proc and /tmp.
+    Namespaces = 2,
+}
+impl Isolation {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Isolation::Unspecified => "ISOLATION_UNSPECIFIED",
+            Isolation::None => "ISOLATION_NONE",
+            Isolation::Namespaces => "ISOLATION_NAMESPACES",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "ISOLATION_UNSPECIFIED" => Some(Self::Unspecified),
+            "ISOLATION_NONE" => Some(Self::None),
+            "ISOLATION_NAMESPACES" => Some(Self::Namespaces),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum StopOutcome {
+    Unspecified = 0,
+    /// Exited with a status code after SIGTERM.
+    ExitedCleanly = 1,
+    /// Died from SIGTERM.
+    Terminated = 2,
+    /// Outlived the grace period and was killed with SIGKILL.
+    Killed = 3,
+    /// Stopped while no attempt was running.
+    Cancelled = 4,
+}
+impl StopOutcome {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            StopOutcome::Unspecified => "STOP_OUTCOME_UNSPECIFIED",
+            StopOutcome::ExitedCleanly => "STOP_OUTCOME_EXITED_CLEANLY",
+            StopOutcome::Terminated => "STOP_OUTCOME_TERMINATED",
+            StopOutcome::Killed => "STOP_OUTCOME_KILLED",
+            StopOutcome::Cancelled => "STOP_OUTCOME_CANCELLED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "STOP_OUTCOME_UNSPECIFIED" => Some(Self::Unspecified),
+            "STOP_OUTCOME_EXITED_CLEANLY" => Some(Self::ExitedCleanly),
+            "STOP_OUTCOME_TERMINATED" => Some(Self::Terminated),
+            "STOP_OUTCOME_KILLED" => Some(Self::Killed),
+            "STOP_OUTCOME_CANCELLED" => Some(Self::Cancelled),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum JobState {
+    Unspecified = 0,
+    Queued = 1,
+    Running = 2,
+    Succeeded = 3,
+    Failed = 4,
+    Stopped = 5,
+    TimedOut = 6,
+    Retrying = 7,
+    /// The server went down before the job finished.
+    Lost = 8,
+}
+impl JobState {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            JobState::Unspecified => "JOB_STATE_UNSPECIFIED",
+            JobState::Queued => "JOB_STATE_QUEUED",
+            JobState::Running => "JOB_STATE_RUNNING",
+            JobState::Succeeded => "JOB_STATE_SUCCEEDED",
+            JobState::Failed => "JOB_STATE_FAILED",
+            JobState::Stopped => "JOB_STATE_STOPPED",
+            JobState::TimedOut => "JOB_STATE_TIMED_OUT",
+            JobState::Retrying => "JOB_STATE_RETRYING",
+            JobState::Lost => "JOB_STATE_LOST",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "JOB_STATE_UNSPECIFIED" => Some(Self::Unspecified),
+            "JOB_STATE_QUEUED" => Some(Self::Queued),
+            "JOB_STATE_RUNNING" => Some(Self::Running),
+            "JOB_STATE_SUCCEEDED" => Some(Self::Succeeded),
+            "JOB_STATE_FAILED" => Some(Self::Failed),
+            "JOB_STATE_STOPPED" => Some(Self::Stopped),
+            "JOB_STATE_TIMED_OUT" => Some(Self::TimedOut),
+            "JOB_STATE_RETRYING" => Some(Self::Retrying),
+            "JOB_STATE_LOST" => Some(Self::Lost),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum OutputStream {
+    Unspecified = 0,
+    Stdout = 1,
+    Stderr = 2,
+}
+impl OutputStream {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            OutputStream::Unspecified => "OUTPUT_STREAM_UNSPECIFIED",
+            OutputStream::Stdout => "OUTPUT_STREAM_STDOUT",
+            OutputStream::Stderr => "OUTPUT_STREAM_STDERR",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "OUTPUT_STREAM_UNSPECIFIED" => Some(Self::Unspecified),
+            "OUTPUT_STREAM_STDOUT" => Some(Self::Stdout),
+            "OUTPUT_STREAM_STDERR" => Some(Self::Stderr),
+            _ => None,
+        }
+    }
 }
 /// Generated client implementations.
 pub mod work_flow_client {
@@ -144,6 +579,50 @@ pub mod work_flow_client {
             self.inner = self.inner.max_encoding_message_size(limit);
             self
         }
+        pub async fn start_job(
+            &mut self,
+            request: impl tonic::IntoRequest<super::StartJobRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::StartJobResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/demo.WorkFlow/StartJob");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("demo.WorkFlow", "StartJob"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn stop_job(
+            &mut self,
+            request: impl tonic::IntoRequest<super::StopJobRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::StopJobResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/demo.WorkFlow/StopJob");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("demo.WorkFlow", "StopJob"));
+            self.inner.unary(req, path, codec).await
+        }
         pub async fn get_job_status(
             &mut self,
             request: impl tonic::IntoRequest<super::JobStatusRequest>,
@@ -169,6 +648,75 @@ pub mod work_flow_client {
                 .insert(GrpcMethod::new("demo.WorkFlow", "GetJobStatus"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn stream_output(
+            &mut self,
+            request: impl tonic::IntoRequest<super::StreamOutputRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::OutputChunk>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/demo.WorkFlow/StreamOutput",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("demo.WorkFlow", "StreamOutput"));
+            self.inner.server_streaming(req, path, codec).await
+        }
+        pub async fn list_jobs(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListJobsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListJobsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/demo.WorkFlow/ListJobs");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("demo.WorkFlow", "ListJobs"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn watch_jobs(
+            &mut self,
+            request: impl tonic::IntoRequest<super::WatchJobsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::JobStateChange>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/demo.WorkFlow/WatchJobs");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("demo.WorkFlow", "WatchJobs"));
+            self.inner.server_streaming(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -178,6 +726,17 @@ pub mod work_flow_server {
     /// Generated trait containing gRPC methods that should be implemented for use with WorkFlowServer.
     #[async_trait]
     pub trait WorkFlow: Send + Sync + 'static {
+        async fn start_job(
+            &self,
+            request: tonic::Request<super::StartJobRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::StartJobResponse>,
+            tonic::Status,
+        >;
+        async fn stop_job(
+            &self,
+            request: tonic::Request<super::StopJobRequest>,
+        ) -> std::result::Result<tonic::Response<super::StopJobResponse>, tonic::Status>;
         async fn get_job_status(
             &self,
             request: tonic::Request<super::JobStatusRequest>,
@@ -185,6 +744,36 @@ pub mod work_flow_server {
             tonic::Response<super::JobStatusResponse>,
             tonic::Status,
         >;
+        /// Server streaming response type for the StreamOutput method.
+        type StreamOutputStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::OutputChunk, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        async fn stream_output(
+            &self,
+            request: tonic::Request<super::StreamOutputRequest>,
+        ) -> std::result::Result<
+            tonic::Response<Self::StreamOutputStream>,
+            tonic::Status,
+        >;
+        async fn list_jobs(
+            &self,
+            request: tonic::Request<super::ListJobsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListJobsResponse>,
+            tonic::Status,
+        >;
+        /// Server streaming response type for the WatchJobs method.
+        type WatchJobsStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::JobStateChange, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        async fn watch_jobs(
+            &self,
+            request: tonic::Request<super::WatchJobsRequest>,
+        ) -> std::result::Result<tonic::Response<Self::WatchJobsStream>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct WorkFlowServer<T: WorkFlow> {
@@ -265,6 +854,94 @@ pub mod work_flow_server {
         fn call(&mut self, req: http::Request<B>) -> Self::Future {
             let inner = self.inner.clone();
             match req.uri().path() {
+                "/demo.WorkFlow/StartJob" => {
+                    #[allow(non_camel_case_types)]
+                    struct StartJobSvc<T: WorkFlow>(pub Arc<T>);
+                    impl<T: WorkFlow> tonic::server::UnaryService<super::StartJobRequest>
+                    for StartJobSvc<T> {
+                        type Response = super::StartJobResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StartJobRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as WorkFlow>::start_job(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = StartJobSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/demo.WorkFlow/StopJob" => {
+                    #[allow(non_camel_case_types)]
+                    struct StopJobSvc<T: WorkFlow>(pub Arc<T>);
+                    impl<T: WorkFlow> tonic::server::UnaryService<super::StopJobRequest>
+                    for StopJobSvc<T> {
+                        type Response = super::StopJobResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StopJobRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as WorkFlow>::stop_job(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = StopJobSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 "/demo.WorkFlow/GetJobStatus" => {
                     #[allow(non_camel_case_types)]
                     struct GetJobStatusSvc<T: WorkFlow>(pub Arc<T>);
@@ -311,6 +988,144 @@ pub mod work_flow_server {
                     };
                     Box::pin(fut)
                 }
+                "/demo.WorkFlow/StreamOutput" => {
+                    #[allow(non_camel_case_types)]
+                    struct StreamOutputSvc<T: WorkFlow>(pub Arc<T>);
+                    impl<
+                        T: WorkFlow,
+                    > tonic::server::ServerStreamingService<super::StreamOutputRequest>
+                    for StreamOutputSvc<T> {
+                        type Response = super::OutputChunk;
+                        type ResponseStream = T::StreamOutputStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StreamOutputRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as WorkFlow>::stream_output(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = StreamOutputSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/demo.WorkFlow/ListJobs" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListJobsSvc<T: WorkFlow>(pub Arc<T>);
+                    impl<T: WorkFlow> tonic::server::UnaryService<super::ListJobsRequest>
+                    for ListJobsSvc<T> {
+                        type Response = super::ListJobsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListJobsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as WorkFlow>::list_jobs(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListJobsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/demo.WorkFlow/WatchJobs" => {
+                    #[allow(non_camel_case_types)]
+                    struct WatchJobsSvc<T: WorkFlow>(pub Arc<T>);
+                    impl<
+                        T: WorkFlow,
+                    > tonic::server::ServerStreamingService<super::WatchJobsRequest>
+                    for WatchJobsSvc<T> {
+                        type Response = super::JobStateChange;
+                        type ResponseStream = T::WatchJobsStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::WatchJobsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as WorkFlow>::watch_jobs(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = WatchJobsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
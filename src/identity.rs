@@ -0,0 +1,87 @@
+//! Client authentication.
+//!
+//! [`authenticate`] is a tonic interceptor that parses the peer certificate
+//! once per request and attaches the resulting [`ClientIdentity`] to the
+//! request extensions. Requests without a valid client certificate are
+//! rejected before they reach the service.
+
+use sha2::{Digest, Sha256};
+use tonic::{Request, Status};
+use tracing::debug;
+
+use crate::auth::{self, AuthError, Role, Rpc};
+
+/// Who is calling, as established by the client certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Subject common name; also the owner name of the jobs the client starts.
+    pub cn: String,
+    pub roles: Vec<Role>,
+    /// Certificate serial number, colon-separated hex.
+    pub serial: String,
+    /// SHA-256 of the DER-encoded certificate, lowercase hex.
+    pub fingerprint: String,
+}
+
+impl ClientIdentity {
+    /// Build the identity from a DER-encoded client certificate.
+    pub fn from_der(der: &[u8]) -> Result<Self, AuthError> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| AuthError::InvalidCertificate(e.to_string()))?;
+        let cn = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|attr| attr.as_str().ok())
+            .map(|s| s.to_string())
+            .ok_or(AuthError::MissingCommonName)?;
+        let roles = auth::roles_from_certificate(&cert)?;
+        let fingerprint = Sha256::digest(der)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        Ok(Self {
+            cn,
+            roles,
+            serial: cert.raw_serial_as_string(),
+            fingerprint,
+        })
+    }
+
+    /// Check that this client may call `rpc`.
+    pub fn authorize(&self, rpc: Rpc) -> Result<(), AuthError> {
+        auth::authorize(rpc, &self.roles)
+    }
+
+    /// Check that this client may access job `job_id` owned by `owner`.
+    pub fn authorize_job(&self, job_id: &str, owner: &str) -> Result<(), AuthError> {
+        auth::authorize_job(&self.cn, &self.roles, job_id, owner)
+    }
+
+    /// Check that this client may access all jobs owned by `owner`.
+    pub fn authorize_owner(&self, owner: &str) -> Result<(), AuthError> {
+        auth::authorize_owner(&self.cn, &self.roles, owner)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.roles.contains(&Role::Admin)
+    }
+}
+
+/// Interceptor authenticating every request by its client certificate.
+#[allow(clippy::result_large_err)]
+pub fn authenticate(mut request: Request<()>) -> Result<Request<()>, Status> {
+    let certs = request
+        .peer_certs()
+        .ok_or_else(|| Status::unauthenticated(AuthError::MissingCertificate.to_string()))?;
+    let cert = certs
+        .first()
+        .ok_or_else(|| Status::unauthenticated(AuthError::MissingCertificate.to_string()))?;
+    let identity = ClientIdentity::from_der(cert.as_ref())
+        .map_err(|e| Status::unauthenticated(e.to_string()))?;
+    debug!(cn = %identity.cn, roles = ?identity.roles, serial = %identity.serial, "authenticated client");
+
+    request.extensions_mut().insert(identity);
+    Ok(request)
+}
@@ -0,0 +1,194 @@
+//! Process isolation of jobs.
+//!
+//! With [`Isolation::Namespaces`], every attempt runs in new PID, mount,
+//! network and UTS namespaces: it only sees its own processes through a
+//! private `/proc`, gets an empty tmpfs on `/tmp`, has no network besides an
+//! unconfigured loopback device, and has the job ID as hostname.
+//!
+//! The process the worker spawns stays behind in the server's namespaces and
+//! mirrors the exit status of the job, so that the worker waits for and
+//! signals an isolated job like any other:
+//!
+//! ```text
+//! spawned process   server namespaces, waits for init
+//! └─ init           PID 1, mounts /proc and /tmp, reaps orphans
+//!    └─ job         PID 2, execs the command
+//! ```
+//!
+//! Signals sent to the job's process group reach the job. The two processes
+//! above it ignore them, except for SIGKILL, which takes down the whole
+//! namespace. So does the job exiting.
+
+use std::fmt;
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::str::FromStr;
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::mount::{mount, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::signal::{self, kill, SigHandler, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, getpid, pipe, read, sethostname, write, ForkResult, Pid};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("unknown isolation mode `{0}` (expected `none` or `namespaces`)")]
+pub struct UnknownIsolation(String);
+
+/// How a job is isolated from the host and from other jobs, ordered from
+/// weakest to strongest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Isolation {
+    /// The job shares the server's namespaces.
+    #[default]
+    None,
+    /// The job gets its own PID, mount, network and UTS namespaces.
+    Namespaces,
+}
+
+impl FromStr for Isolation {
+    type Err = UnknownIsolation;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Isolation::None),
+            "namespaces" => Ok(Isolation::Namespaces),
+            other => Err(UnknownIsolation(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Isolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Isolation::None => f.write_str("none"),
+            Isolation::Namespaces => f.write_str("namespaces"),
+        }
+    }
+}
+
+/// Move the calling process into new namespaces with `hostname`. Meant to
+/// run between fork and exec of a job, so it only makes async-signal-safe
+/// calls. Returns in the process that is to exec the job; the processes
+/// left behind never return.
+pub(crate) fn enter_namespaces(hostname: &str) -> io::Result<()> {
+    // Tells the spawned process which signal killed the job, see `init`.
+    let (signal_rx, signal_tx) = pipe()?;
+    unshare(
+        CloneFlags::CLONE_NEWPID
+            | CloneFlags::CLONE_NEWNS
+            | CloneFlags::CLONE_NEWNET
+            | CloneFlags::CLONE_NEWUTS,
+    )?;
+    // SAFETY: the child only makes async-signal-safe calls until it execs.
+    if let ForkResult::Parent { child } = unsafe { fork() }? {
+        drop(signal_tx);
+        mirror(child, signal_rx);
+    }
+    drop(signal_rx);
+
+    // Keep the mounts below from propagating back to the host.
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )?;
+    mount(
+        Some("proc"),
+        "/proc",
+        Some("proc"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        None::<&str>,
+    )?;
+    mount(
+        Some("tmpfs"),
+        "/tmp",
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some("mode=1777"),
+    )?;
+    sethostname(hostname)?;
+
+    // SAFETY: as above.
+    if let ForkResult::Parent { child } = unsafe { fork() }? {
+        init(child, signal_tx);
+    }
+    drop(signal_tx);
+    Ok(())
+}
+
+/// Wait, in the server's namespaces, for `init` and exit the way the job
+/// did.
+fn mirror(init: Pid, signal_rx: OwnedFd) -> ! {
+    // Signals meant for the job must not end this process before the job.
+    let _ = signal::sigprocmask(SigmaskHow::SIG_BLOCK, Some(&SigSet::all()), None);
+    close_fds_except(signal_rx.as_raw_fd());
+    loop {
+        match waitpid(init, None) {
+            Ok(WaitStatus::Exited(_, code)) => {
+                let mut signal = [0];
+                if let Ok(1) = read(signal_rx.as_raw_fd(), &mut signal) {
+                    if let Ok(signal) = Signal::try_from(signal[0] as i32) {
+                        exit_by(signal);
+                    }
+                }
+                exit(code);
+            }
+            Ok(WaitStatus::Signaled(_, signal, _)) => exit_by(signal),
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(_) => exit(127),
+        }
+    }
+}
+
+/// Reap every process of the namespace until `job` has exited, then exit
+/// the way it did. PID 1 cannot be killed by a signal it has no handler for,
+/// so a signal that killed the job is passed up through `signal_tx`.
+fn init(job: Pid, signal_tx: OwnedFd) -> ! {
+    close_fds_except(signal_tx.as_raw_fd());
+    loop {
+        match waitpid(None, None) {
+            Ok(WaitStatus::Exited(pid, code)) if pid == job => exit(code),
+            Ok(WaitStatus::Signaled(pid, signal, _)) if pid == job => {
+                let _ = write(&signal_tx, &[signal as u8]);
+                exit(128 + signal as i32);
+            }
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(_) => exit(127),
+        }
+    }
+}
+
+/// Close every file descriptor but `keep`, so that the job's output pipes
+/// and the exec status pipe of the spawning server only stay open in the job.
+fn close_fds_except(keep: RawFd) {
+    let keep = keep as libc::c_uint;
+    // SAFETY: plain system calls on descriptors nothing else in this process uses.
+    unsafe {
+        if keep > 0 {
+            libc::close_range(0, keep - 1, 0);
+        }
+        libc::close_range(keep + 1, libc::c_uint::MAX, 0);
+    }
+}
+
+fn exit_by(signal: Signal) -> ! {
+    // SAFETY: restoring the default disposition is async-signal-safe.
+    let _ = unsafe { signal::signal(signal, SigHandler::SigDfl) };
+    let mut unblock = SigSet::empty();
+    unblock.add(signal);
+    let _ = signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&unblock), None);
+    let _ = kill(getpid(), signal);
+    exit(128 + signal as i32)
+}
+
+fn exit(code: i32) -> ! {
+    // SAFETY: _exit skips the atexit handlers and destructors of the server
+    // process this one was forked from.
+    unsafe { libc::_exit(code) }
+}
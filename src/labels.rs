@@ -0,0 +1,263 @@
+//! Job labels and annotations.
+//!
+//! Both are string maps attached to a job when it is submitted. Keys are an
+//! optional DNS subdomain prefix followed by `/`, then a name of at most 63
+//! alphanumerics, `-`, `_` and `.` that starts and ends with an alphanumeric,
+//! e.g. `team`, `ci.example.com/pipeline`. Label values follow the syntax of
+//! names but may be empty, since they are matched by a [`Selector`];
+//! annotation values are free-form.
+
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+const MAX_NAME_LEN: usize = 63;
+const MAX_PREFIX_LEN: usize = 253;
+
+#[derive(Debug, Error)]
+pub enum LabelError {
+    #[error("invalid key `{key}`: {reason}")]
+    InvalidKey { key: String, reason: &'static str },
+    #[error("invalid value `{value}` of label `{key}`: {reason}")]
+    InvalidValue {
+        key: String,
+        value: String,
+        reason: &'static str,
+    },
+    #[error("invalid label selector `{selector}`: {reason}")]
+    InvalidSelector {
+        selector: String,
+        reason: &'static str,
+    },
+}
+
+/// Check every key and value of a label map.
+pub fn validate_labels(labels: &BTreeMap<String, String>) -> Result<(), LabelError> {
+    for (key, value) in labels {
+        validate_key(key)?;
+        validate_label_value(key, value)?;
+    }
+    Ok(())
+}
+
+/// Check every key of an annotation map.
+pub fn validate_annotations(annotations: &BTreeMap<String, String>) -> Result<(), LabelError> {
+    annotations.keys().try_for_each(|key| validate_key(key))
+}
+
+pub fn validate_key(key: &str) -> Result<(), LabelError> {
+    let invalid = |reason| LabelError::InvalidKey {
+        key: key.to_string(),
+        reason,
+    };
+    let name = match key.split_once('/') {
+        Some((prefix, name)) => {
+            if prefix.is_empty() || prefix.len() > MAX_PREFIX_LEN {
+                return Err(invalid("prefix must be 1 to 253 characters"));
+            }
+            if !prefix.split('.').all(is_dns_label) {
+                return Err(invalid("prefix must be a DNS subdomain"));
+            }
+            name
+        }
+        None => key,
+    };
+    if name.is_empty() {
+        return Err(invalid("name must not be empty"));
+    }
+    check_name(name).map_err(invalid)
+}
+
+pub fn validate_label_value(key: &str, value: &str) -> Result<(), LabelError> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    check_name(value).map_err(|reason| LabelError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason,
+    })
+}
+
+fn check_name(name: &str) -> Result<(), &'static str> {
+    if name.len() > MAX_NAME_LEN {
+        return Err("must be at most 63 characters");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err("may only contain alphanumerics, `-`, `_` and `.`");
+    }
+    let alphanumeric_at = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    if !alphanumeric_at(name.chars().next()) || !alphanumeric_at(name.chars().last()) {
+        return Err("must start and end with an alphanumeric");
+    }
+    Ok(())
+}
+
+fn is_dns_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= MAX_NAME_LEN
+        && label
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+}
+
+/// A Kubernetes-style equality-based label selector: comma-separated
+/// requirements that must all hold, each one of `key=value` (or
+/// `key==value`), `key!=value`, `key` (the label exists) and `!key` (it does
+/// not). The empty selector matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Self, LabelError> {
+        let mut requirements = Vec::new();
+        for term in selector.split(',').map(str::trim) {
+            if term.is_empty() {
+                if selector.trim().is_empty() {
+                    continue;
+                }
+                return Err(LabelError::InvalidSelector {
+                    selector: selector.to_string(),
+                    reason: "empty requirement",
+                });
+            }
+            let requirement = if let Some((key, value)) = term.split_once("!=") {
+                Requirement::NotEquals(key.trim().to_string(), value.trim().to_string())
+            } else if let Some((key, value)) = term.split_once("==").or(term.split_once('=')) {
+                Requirement::Equals(key.trim().to_string(), value.trim().to_string())
+            } else if let Some(key) = term.strip_prefix('!') {
+                Requirement::NotExists(key.trim().to_string())
+            } else {
+                Requirement::Exists(term.to_string())
+            };
+            match &requirement {
+                Requirement::Equals(key, value) | Requirement::NotEquals(key, value) => {
+                    validate_key(key)?;
+                    validate_label_value(key, value)?;
+                }
+                Requirement::Exists(key) | Requirement::NotExists(key) => validate_key(key)?,
+            }
+            requirements.push(requirement);
+        }
+        Ok(Self { requirements })
+    }
+
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| match requirement {
+                Requirement::Equals(key, value) => labels.get(key) == Some(value),
+                Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+                Requirement::Exists(key) => labels.contains_key(key),
+                Requirement::NotExists(key) => !labels.contains_key(key),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn matches(selector: &str, pairs: &[(&str, &str)]) -> bool {
+        Selector::parse(selector).unwrap().matches(&labels(pairs))
+    }
+
+    #[test]
+    fn equality() {
+        assert!(matches("team=infra", &[("team", "infra")]));
+        assert!(matches("team==infra", &[("team", "infra")]));
+        assert!(!matches("team=infra", &[("team", "web")]));
+        assert!(!matches("team=infra", &[]));
+        assert!(matches("team=", &[("team", "")]));
+    }
+
+    #[test]
+    fn inequality_also_matches_missing_labels() {
+        assert!(matches("env!=prod", &[("env", "dev")]));
+        assert!(matches("env!=prod", &[]));
+        assert!(!matches("env!=prod", &[("env", "prod")]));
+    }
+
+    #[test]
+    fn existence() {
+        assert!(matches("team", &[("team", "infra")]));
+        assert!(!matches("team", &[("env", "prod")]));
+        assert!(matches("!team", &[("env", "prod")]));
+        assert!(!matches("!team", &[("team", "infra")]));
+    }
+
+    #[test]
+    fn every_requirement_must_hold() {
+        let job = [("team", "infra"), ("env", "dev")];
+        assert!(matches(" team = infra , env!=prod, !owner", &job));
+        assert!(!matches("team=infra,env=prod", &job));
+        assert!(matches("example.com/tier=a", &[("example.com/tier", "a")]));
+    }
+
+    #[test]
+    fn empty_selector_matches_everything() {
+        assert_eq!(Selector::parse("").unwrap(), Selector::default());
+        assert!(matches("  ", &[("team", "infra")]));
+    }
+
+    #[test]
+    fn rejects_empty_requirements() {
+        for selector in ["team=infra,", ",team=infra", "a,,b"] {
+            let err = Selector::parse(selector).unwrap_err();
+            assert!(
+                matches!(err, LabelError::InvalidSelector { .. }),
+                "{}: {}",
+                selector,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_keys_and_values() {
+        for selector in ["=infra", "-team=infra", "Team.Example/x=y", "!", "a b=c"] {
+            let err = Selector::parse(selector).unwrap_err();
+            assert!(
+                matches!(err, LabelError::InvalidKey { .. }),
+                "{}: {}",
+                selector,
+                err
+            );
+        }
+        let err = Selector::parse("team=in fra").unwrap_err();
+        assert!(matches!(err, LabelError::InvalidValue { .. }), "{}", err);
+    }
+
+    #[test]
+    fn validates_keys() {
+        assert!(validate_key("team").is_ok());
+        assert!(validate_key("ci.example.com/pipeline").is_ok());
+        assert!(validate_key(&"a".repeat(MAX_NAME_LEN)).is_ok());
+        assert!(validate_key(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+        assert!(validate_key("/team").is_err());
+        assert!(validate_key("example.com/").is_err());
+        assert!(validate_key("team_").is_err());
+    }
+}
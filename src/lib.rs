@@ -1 +1,18 @@
+pub mod auth;
+pub mod cgroup;
+pub mod config;
+pub mod events;
+pub mod identity;
+pub mod isolation;
+pub mod labels;
+mod notify;
+pub mod output;
+pub mod retry;
+pub mod scheduler;
+pub mod state;
+pub mod store;
+pub mod tls;
+pub mod users;
+pub mod worker;
+
 pub type Result<T> = anyhow::Result<T>;
@@ -0,0 +1,44 @@
+//! Wake-up signal between an append-only log and its readers.
+//!
+//! Readers look at the log under its own lock and, once they have caught up,
+//! wait on a [`Listener`] until the writer calls [`Notifier::notify`]. The
+//! signal carries no data, so writers never wait on readers.
+
+use tokio::sync::watch;
+
+#[derive(Debug)]
+pub(crate) struct Notifier(watch::Sender<()>);
+
+impl Notifier {
+    pub(crate) fn new() -> Self {
+        Self(watch::Sender::new(()))
+    }
+
+    /// Wake up every listener.
+    pub(crate) fn notify(&self) {
+        self.0.send_replace(());
+    }
+
+    pub(crate) fn subscribe(&self) -> Listener {
+        Listener(self.0.subscribe())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Listener(watch::Receiver<()>);
+
+impl Listener {
+    /// Call `poll` until it returns a value, waiting for a notification
+    /// after every `None`. Returns `None` once the notifier is gone.
+    pub(crate) async fn wait_for<T>(&mut self, mut poll: impl FnMut() -> Option<T>) -> Option<T> {
+        loop {
+            // Mark the current version as seen before polling so that a
+            // notification racing with `poll` still wakes us up.
+            self.0.borrow_and_update();
+            if let Some(value) = poll() {
+                return Some(value);
+            }
+            self.0.changed().await.ok()?;
+        }
+    }
+}
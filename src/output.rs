@@ -0,0 +1,838 @@
+//! Per-job output log.
+//!
+//! An [`OutputLog`] is an append-only log holding everything an attempt of a
+//! job has written since it started, either in memory or spooled to a file.
+//! Stdout and stderr share the log, which remembers which stream every byte
+//! came from and when. Any number of [`OutputReader`]s can follow it
+//! concurrently: each one replays the log from a given offset, optionally
+//! skipping one of the streams, and then waits for new output until the log
+//! is closed. Writers never wait on readers, so a slow subscriber cannot
+//! stall the process or other readers.
+//!
+//! A log may be capped at a maximum size, past which further output is
+//! dropped and a truncation marker is written instead. Once the job's
+//! [`OutputPolicy`] no longer retains it, the log is discarded.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::notify::{Listener, Notifier};
+
+/// Upper bound on the size of a single chunk handed out by [`OutputReader::next_chunk`].
+pub const MAX_CHUNK_SIZE: usize = 32 * 1024;
+
+/// Maximum size of the output of an attempt under [`OutputPolicy::default`].
+pub const DEFAULT_MAX_OUTPUT_SIZE: u64 = 16 * 1024 * 1024;
+/// Output retention period of [`OutputPolicy::default`].
+pub const DEFAULT_OUTPUT_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+/// Number of finished jobs whose output [`OutputPolicy::default`] retains.
+pub const DEFAULT_RETAINED_OUTPUTS: usize = 1000;
+
+/// How much job output is kept, and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputPolicy {
+    /// Size at which the output of an attempt is truncated; `None` keeps
+    /// everything.
+    pub max_size: Option<u64>,
+    /// How long the output of a finished job is kept; `None` keeps it
+    /// forever.
+    pub max_age: Option<Duration>,
+    /// How many finished jobs keep their output, most recently finished
+    /// first; `None` keeps every job's.
+    pub max_jobs: Option<usize>,
+}
+
+impl Default for OutputPolicy {
+    fn default() -> Self {
+        Self {
+            max_size: Some(DEFAULT_MAX_OUTPUT_SIZE),
+            max_age: Some(DEFAULT_OUTPUT_RETENTION),
+            max_jobs: Some(DEFAULT_RETAINED_OUTPUTS),
+        }
+    }
+}
+
+/// Pipe of a job's process that output was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A piece of output handed out by [`OutputReader::next_chunk`], all from
+/// the same stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub stream: OutputStream,
+    /// Offset of `data` in the log, counting both streams.
+    pub offset: u64,
+    /// Time `data` was written at, in milliseconds since the log was
+    /// created.
+    pub timestamp_ms: u64,
+    pub data: Vec<u8>,
+}
+
+/// Output of one stream written at one point in time, from `offset` up to
+/// the offset of the next segment.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Segment {
+    offset: u64,
+    stream: OutputStream,
+    timestamp_ms: u64,
+}
+
+#[derive(Debug)]
+enum Storage {
+    Memory(Vec<u8>),
+    /// Output spooled to a file, with the segments listed in an index file
+    /// of one JSON object per line if it is to be loaded again.
+    File {
+        // Shared with readers, which read it without holding the buffer.
+        data: Arc<File>,
+        index: Option<File>,
+    },
+    Discarded,
+}
+
+#[derive(Debug)]
+struct Buffer {
+    storage: Storage,
+    segments: Vec<Segment>,
+    // Origin of segment timestamps.
+    created: Instant,
+    len: u64,
+    max_size: Option<u64>,
+    truncated: bool,
+    closed: bool,
+}
+
+impl Buffer {
+    fn write(&mut self, stream: OutputStream, timestamp_ms: u64, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() || matches!(self.storage, Storage::Discarded) {
+            return Ok(());
+        }
+        // Output of the same stream within the same millisecond extends the
+        // last segment rather than starting one.
+        let extends = self
+            .segments
+            .last()
+            .is_some_and(|last| last.stream == stream && last.timestamp_ms == timestamp_ms);
+        if !extends {
+            let segment = Segment {
+                offset: self.len,
+                stream,
+                timestamp_ms,
+            };
+            if let Storage::File {
+                index: Some(index), ..
+            } = &mut self.storage
+            {
+                let mut line = serde_json::to_vec(&segment)?;
+                line.push(b'\n');
+                index.write_all(&line)?;
+            }
+            self.segments.push(segment);
+        }
+        match &mut self.storage {
+            Storage::Memory(memory) => memory.extend_from_slice(data),
+            Storage::File { data: file, .. } => file.write_all_at(data, self.len)?,
+            Storage::Discarded => {}
+        }
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    /// The segment `offset` falls in, and the offset at which it ends.
+    fn segment_at(&self, offset: u64) -> (Segment, u64) {
+        let next = self
+            .segments
+            .partition_point(|segment| segment.offset <= offset);
+        let segment = match next {
+            0 => Segment {
+                offset: 0,
+                stream: OutputStream::Stdout,
+                timestamp_ms: 0,
+            },
+            next => self.segments[next - 1],
+        };
+        let end = self
+            .segments
+            .get(next)
+            .map_or(self.len, |segment| segment.offset);
+        (segment, end)
+    }
+
+    /// Up to `max` bytes starting at `offset`.
+    fn read(&self, offset: u64, max: usize) -> io::Result<Vec<u8>> {
+        let end = self.len.min(offset + max as u64);
+        match &self.storage {
+            Storage::Memory(memory) => Ok(memory[offset as usize..end as usize].to_vec()),
+            Storage::File { data: file, .. } => {
+                let mut data = vec![0; (end - offset) as usize];
+                file.read_exact_at(&mut data, offset)?;
+                Ok(data)
+            }
+            Storage::Discarded => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Offset at which the last `lines` lines of the first `len` bytes of output
+/// start, reading the output backwards through `read`.
+fn scan_tail(
+    segments: &[Segment],
+    len: u64,
+    lines: usize,
+    stream: Option<OutputStream>,
+    mut read: impl FnMut(u64, usize) -> io::Result<Vec<u8>>,
+) -> io::Result<u64> {
+    let mut newlines = 0;
+    // The newline ending the output does not start another line.
+    let mut last_byte = true;
+    let mut end = len;
+    for segment in segments.iter().rev() {
+        if stream.is_some_and(|stream| stream != segment.stream) {
+            end = segment.offset;
+            continue;
+        }
+        // Scan the segment backwards, one chunk at a time.
+        while end > segment.offset {
+            let start = segment
+                .offset
+                .max(end.saturating_sub(MAX_CHUNK_SIZE as u64));
+            let data = read(start, (end - start) as usize)?;
+            for (i, byte) in data.iter().enumerate().rev() {
+                if *byte == b'\n' && !last_byte {
+                    newlines += 1;
+                    if newlines == lines {
+                        return Ok(start + i as u64 + 1);
+                    }
+                }
+                last_byte = false;
+            }
+            end = start;
+        }
+    }
+    Ok(0)
+}
+
+#[derive(Debug)]
+pub struct OutputLog {
+    buffer: Mutex<Buffer>,
+    // Notified on every append and on close.
+    changed: Notifier,
+}
+
+impl Default for OutputLog {
+    fn default() -> Self {
+        Self::with_storage(Storage::Memory(Vec::new()), Vec::new(), 0, false)
+    }
+}
+
+impl OutputLog {
+    /// An empty log kept in memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty log spooled to `data`, with its segments listed in `index`
+    /// if given.
+    pub fn spooled(data: File, index: Option<File>) -> Self {
+        let storage = Storage::File {
+            data: Arc::new(data),
+            index,
+        };
+        Self::with_storage(storage, Vec::new(), 0, false)
+    }
+
+    /// A closed log holding what was spooled to `data` and `index` before.
+    /// Without an index, all of the output is taken to come from stdout.
+    pub fn load(data: File, index: Option<File>) -> io::Result<Self> {
+        let len = data.metadata()?.len();
+        let mut segments: Vec<Segment> = Vec::new();
+        if let Some(index) = index {
+            for line in BufReader::new(index).lines() {
+                // Skip entries cut short by a crash, and those of output that
+                // never made it to `data`.
+                match serde_json::from_str::<Segment>(&line?) {
+                    Ok(segment)
+                        if segment.offset < len
+                            && segments
+                                .last()
+                                .is_none_or(|last| last.offset < segment.offset) =>
+                    {
+                        segments.push(segment)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let storage = Storage::File {
+            data: Arc::new(data),
+            index: None,
+        };
+        Ok(Self::with_storage(storage, segments, len, true))
+    }
+
+    /// A closed log whose output was discarded.
+    pub fn discarded() -> Self {
+        Self::with_storage(Storage::Discarded, Vec::new(), 0, true)
+    }
+
+    fn with_storage(storage: Storage, segments: Vec<Segment>, len: u64, closed: bool) -> Self {
+        Self {
+            buffer: Mutex::new(Buffer {
+                storage,
+                segments,
+                created: Instant::now(),
+                len,
+                max_size: None,
+                truncated: false,
+                closed,
+            }),
+            changed: Notifier::new(),
+        }
+    }
+
+    /// Truncate the log once it reaches `max_size` bytes.
+    pub fn with_max_size(self, max_size: Option<u64>) -> Self {
+        self.buffer.lock().unwrap().max_size = max_size;
+        self
+    }
+
+    /// Append `data`, written to `stream`, to the log and wake up every
+    /// reader. Returns the offset at which `data` was placed. Output past the
+    /// maximum size is replaced by a single truncation marker.
+    pub fn append(&self, stream: OutputStream, data: &[u8]) -> u64 {
+        let offset = {
+            let mut buffer = self.buffer.lock().unwrap();
+            let offset = buffer.len;
+            let timestamp_ms = buffer.created.elapsed().as_millis() as u64;
+            if data.is_empty() || buffer.closed || buffer.truncated {
+                return offset;
+            }
+            let (fits, marker) = match buffer.max_size {
+                Some(max_size) if offset + data.len() as u64 > max_size => {
+                    let fits = max_size.saturating_sub(offset) as usize;
+                    let marker = format!("\n[output truncated at {} bytes]\n", max_size);
+                    (&data[..fits], Some(marker))
+                }
+                _ => (data, None),
+            };
+            let mut result = buffer.write(stream, timestamp_ms, fits);
+            if let Some(marker) = marker {
+                buffer.truncated = true;
+                result =
+                    result.and_then(|()| buffer.write(stream, timestamp_ms, marker.as_bytes()));
+            }
+            if let Err(e) = result {
+                // Stop writing rather than leaving a hole in the log.
+                warn!("failed to write job output: {}", e);
+                buffer.truncated = true;
+            }
+            offset
+        };
+        self.changed.notify();
+        offset
+    }
+
+    /// Mark the log as complete. Readers drain what is left and then stop.
+    pub fn close(&self) {
+        self.buffer.lock().unwrap().closed = true;
+        self.changed.notify();
+    }
+
+    /// Drop the output and close the log. Readers stop at their next chunk.
+    pub fn discard(&self) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.storage = Storage::Discarded;
+            buffer.segments = Vec::new();
+            buffer.closed = true;
+        }
+        self.changed.notify();
+    }
+
+    pub fn len(&self) -> u64 {
+        self.buffer.lock().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.buffer.lock().unwrap().closed
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.buffer.lock().unwrap().truncated
+    }
+
+    pub fn is_discarded(&self) -> bool {
+        matches!(self.buffer.lock().unwrap().storage, Storage::Discarded)
+    }
+
+    /// Offset at which the last `lines` lines written so far start, counting
+    /// only the output of `stream` unless it is `None`. A line missing its
+    /// final newline counts as one.
+    pub fn tail_offset(&self, lines: usize, stream: Option<OutputStream>) -> io::Result<u64> {
+        let buffer = self.buffer.lock().unwrap();
+        if lines == 0 {
+            return Ok(buffer.len);
+        }
+        match &buffer.storage {
+            // Only the part of the file written so far is read, and it never
+            // changes, so it can be read without blocking the writer.
+            Storage::File { data, .. } => {
+                let file = data.clone();
+                let segments = buffer.segments.clone();
+                let len = buffer.len;
+                drop(buffer);
+                scan_tail(&segments, len, lines, stream, |offset, size| {
+                    let mut data = vec![0; size];
+                    file.read_exact_at(&mut data, offset)?;
+                    Ok(data)
+                })
+            }
+            _ => scan_tail(
+                &buffer.segments,
+                buffer.len,
+                lines,
+                stream,
+                |offset, size| buffer.read(offset, size),
+            ),
+        }
+    }
+
+    /// The chunk of `stream` (or of both streams) at or after `offset`,
+    /// advancing `offset` past it; `Some(None)` if no more output will come,
+    /// `None` if the reader has to wait for more.
+    fn poll_chunk(&self, offset: &mut u64, stream: Option<OutputStream>) -> Option<Option<Chunk>> {
+        let (mut chunk, file) = {
+            let buffer = self.buffer.lock().unwrap();
+            if let Storage::Discarded = buffer.storage {
+                return Some(None);
+            }
+            loop {
+                if *offset >= buffer.len {
+                    return buffer.closed.then_some(None);
+                }
+                let (segment, end) = buffer.segment_at(*offset);
+                if stream.is_some_and(|stream| stream != segment.stream) {
+                    *offset = end;
+                    continue;
+                }
+                let max = MAX_CHUNK_SIZE.min((end - *offset) as usize);
+                let mut chunk = Chunk {
+                    stream: segment.stream,
+                    offset: *offset,
+                    timestamp_ms: segment.timestamp_ms,
+                    data: Vec::new(),
+                };
+                // Output already written never changes, so the file is read
+                // once the writer is free to go on.
+                let file = match &buffer.storage {
+                    Storage::File { data, .. } => {
+                        chunk.data = vec![0; max];
+                        Some(data.clone())
+                    }
+                    _ => match buffer.read(*offset, max) {
+                        Ok(data) => {
+                            chunk.data = data;
+                            None
+                        }
+                        Err(e) => {
+                            warn!("failed to read job output: {}", e);
+                            return Some(None);
+                        }
+                    },
+                };
+                break (chunk, file);
+            }
+        };
+        if let Some(file) = file {
+            if let Err(e) = file.read_exact_at(&mut chunk.data, chunk.offset) {
+                warn!("failed to read job output: {}", e);
+                return Some(None);
+            }
+        }
+        *offset += chunk.data.len() as u64;
+        Some(Some(chunk))
+    }
+
+    /// Follow both streams of the log from its first byte.
+    pub fn subscribe(self: &Arc<Self>) -> OutputReader {
+        OutputReader {
+            log: self.clone(),
+            offset: 0,
+            stream: None,
+            changed: self.changed.subscribe(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OutputReader {
+    log: Arc<OutputLog>,
+    offset: u64,
+    stream: Option<OutputStream>,
+    changed: Listener,
+}
+
+impl OutputReader {
+    /// Start reading at `offset` instead of the first byte.
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Only read the output of `stream`, or of both streams if `None`.
+    pub fn with_stream(mut self, stream: Option<OutputStream>) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    /// Return the next chunk of output, waiting for the job to write more if
+    /// the reader has caught up. Returns `None` once the log is closed and
+    /// fully read, or discarded.
+    pub async fn next_chunk(&mut self) -> Option<Chunk> {
+        let (log, offset, stream) = (&self.log, &mut self.offset, self.stream);
+        self.changed
+            .wait_for(|| log.poll_chunk(offset, stream))
+            .await
+            .flatten()
+    }
+
+    /// Send every chunk through `tx`, turned into a message by `message`,
+    /// until the log is closed and fully read, or discarded. Stops as soon
+    /// as the receiver goes away, even while waiting for more output.
+    pub async fn forward<T>(mut self, tx: &mpsc::Sender<T>, mut message: impl FnMut(Chunk) -> T) {
+        loop {
+            let chunk = tokio::select! {
+                chunk = self.next_chunk() => chunk,
+                _ = tx.closed() => return,
+            };
+            let Some(chunk) = chunk else {
+                return;
+            };
+            if tx.send(message(chunk)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+
+    use super::*;
+
+    use OutputStream::{Stderr, Stdout};
+
+    /// A file of the temporary directory that is gone once closed.
+    fn spool_file() -> File {
+        let path = std::env::temp_dir().join(format!("output-test-{:016x}", rand::random::<u64>()));
+        let file = OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        file
+    }
+
+    /// An empty log of every kind of storage.
+    fn logs() -> [OutputLog; 2] {
+        [OutputLog::new(), OutputLog::spooled(spool_file(), None)]
+    }
+
+    /// Everything `reader` reads until the log is closed.
+    async fn read_all(mut reader: OutputReader) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        while let Some(chunk) = reader.next_chunk().await {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    fn data(chunks: &[Chunk]) -> Vec<u8> {
+        chunks.iter().flat_map(|chunk| chunk.data.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn output_past_the_maximum_size_is_replaced_by_a_marker() {
+        for log in logs() {
+            let log = Arc::new(log.with_max_size(Some(10)));
+            assert_eq!(log.append(Stdout, b"01234"), 0);
+            assert_eq!(log.append(Stderr, b"56789abc"), 5);
+            assert!(log.is_truncated());
+            // Nothing gets past the marker, not even output that would fit.
+            log.append(Stdout, b"d");
+            log.close();
+
+            let marker = "\n[output truncated at 10 bytes]\n";
+            assert_eq!(log.len(), 10 + marker.len() as u64);
+            let chunks = read_all(log.subscribe()).await;
+            assert_eq!(data(&chunks), format!("0123456789{}", marker).as_bytes());
+            assert_eq!(chunks.last().unwrap().stream, Stderr);
+        }
+    }
+
+    #[tokio::test]
+    async fn output_that_reaches_the_maximum_size_exactly_is_kept_whole() {
+        for log in logs() {
+            let log = Arc::new(log.with_max_size(Some(4)));
+            log.append(Stdout, b"0123");
+            assert!(!log.is_truncated());
+            log.append(Stdout, b"4");
+            assert!(log.is_truncated());
+            log.close();
+            let chunks = read_all(log.subscribe()).await;
+            assert!(data(&chunks).starts_with(b"0123\n[output truncated at 4 bytes]"));
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_without_a_maximum_size_keep_everything() {
+        for log in logs() {
+            let log = Arc::new(log);
+            let line = [b'x'; 1000];
+            for _ in 0..100 {
+                log.append(Stdout, &line);
+            }
+            log.close();
+            assert!(!log.is_truncated());
+            assert_eq!(data(&read_all(log.subscribe()).await).len(), 100_000);
+        }
+    }
+
+    #[tokio::test]
+    async fn discarded_logs_stop_their_readers() {
+        for log in logs() {
+            let log = Arc::new(log);
+            log.append(Stdout, b"hello");
+            let reader = log.subscribe();
+            log.discard();
+            assert!(log.is_discarded() && log.is_closed());
+            assert!(read_all(reader).await.is_empty());
+        }
+    }
+
+    /// A closed log of `writes`, in every kind of storage.
+    fn written(writes: &[(OutputStream, &[u8])]) -> [Arc<OutputLog>; 2] {
+        logs().map(|log| {
+            for (stream, data) in writes {
+                log.append(*stream, data);
+            }
+            log.close();
+            Arc::new(log)
+        })
+    }
+
+    /// The output from the last `lines` lines of `stream` on.
+    async fn tail(log: &Arc<OutputLog>, lines: usize, stream: Option<OutputStream>) -> Vec<u8> {
+        let offset = log.tail_offset(lines, stream).unwrap();
+        data(&read_all(log.subscribe().with_offset(offset).with_stream(stream)).await)
+    }
+
+    #[tokio::test]
+    async fn tails_count_a_final_line_with_or_without_its_newline() {
+        for log in written(&[(Stdout, b"a\nb\nc\n")]) {
+            assert_eq!(tail(&log, 2, None).await, b"b\nc\n");
+            assert_eq!(tail(&log, 3, None).await, b"a\nb\nc\n");
+            assert_eq!(tail(&log, 10, None).await, b"a\nb\nc\n");
+            assert_eq!(log.tail_offset(0, None).unwrap(), log.len());
+        }
+        for log in written(&[(Stdout, b"a\nb\nc")]) {
+            assert_eq!(tail(&log, 1, None).await, b"c");
+            assert_eq!(tail(&log, 2, None).await, b"b\nc");
+        }
+        for log in written(&[]) {
+            assert_eq!(log.tail_offset(5, None).unwrap(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn tails_of_one_stream_skip_the_other() {
+        let writes: [(OutputStream, &[u8]); 4] = [
+            (Stdout, b"out 1\nout "),
+            (Stderr, b"err 1\nerr 2\n"),
+            (Stdout, b"2\nout 3\n"),
+            (Stderr, b"err 3\n"),
+        ];
+        for log in written(&writes) {
+            assert_eq!(tail(&log, 2, Some(Stdout)).await, b"out 2\nout 3\n");
+            assert_eq!(tail(&log, 1, Some(Stderr)).await, b"err 3\n");
+            assert_eq!(tail(&log, 3, Some(Stderr)).await, b"err 1\nerr 2\nerr 3\n");
+            assert_eq!(tail(&log, 2, None).await, b"out 3\nerr 3\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn tails_span_chunk_boundaries() {
+        // A line straddling the boundary of the chunks the scan reads the
+        // output in, followed by a line of exactly one chunk.
+        let mut first = vec![b'a'; MAX_CHUNK_SIZE + 10];
+        first.push(b'\n');
+        let mut second = vec![b'b'; MAX_CHUNK_SIZE - 1];
+        second.push(b'\n');
+        for log in written(&[(Stdout, b"head\n"), (Stdout, &first), (Stdout, &second)]) {
+            assert_eq!(tail(&log, 1, None).await, second);
+            assert_eq!(
+                tail(&log, 2, None).await,
+                [&first[..], &second[..]].concat()
+            );
+            assert_eq!(log.tail_offset(3, None).unwrap(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn readers_replay_the_log_then_follow_it() {
+        for log in logs() {
+            let log = Arc::new(log);
+            log.append(Stdout, b"before\n");
+            let reader = tokio::spawn(read_all(log.subscribe()));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            log.append(Stdout, b"after\n");
+            log.close();
+            assert_eq!(data(&reader.await.unwrap()), b"before\nafter\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_readers_each_get_all_of_the_output() {
+        for log in logs() {
+            let log = Arc::new(log);
+            let readers: Vec<_> = (0..4)
+                .map(|_| tokio::spawn(read_all(log.subscribe())))
+                .collect();
+            let mut expected = Vec::new();
+            for i in 0..200 {
+                let line = format!("line {}\n", i);
+                log.append(Stdout, line.as_bytes());
+                expected.extend_from_slice(line.as_bytes());
+                if i % 50 == 0 {
+                    tokio::task::yield_now().await;
+                }
+            }
+            log.close();
+            for reader in readers {
+                assert_eq!(data(&reader.await.unwrap()), expected);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn readers_resume_at_an_offset() {
+        for log in written(&[(Stdout, b"0123"), (Stderr, b"4567"), (Stdout, b"89")]) {
+            let chunks = read_all(log.subscribe().with_offset(6)).await;
+            assert_eq!(data(&chunks), b"6789");
+            assert_eq!(chunks[0].offset, 6);
+            assert_eq!(chunks[0].stream, Stderr);
+            assert_eq!(chunks[1].offset, 8);
+            // Resuming at the end of a closed log reads nothing.
+            assert!(read_all(log.subscribe().with_offset(10)).await.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn forwarding_stops_when_the_receiver_goes_away() {
+        let log = Arc::new(OutputLog::new());
+        log.append(Stdout, b"hello");
+        let (tx, mut rx) = mpsc::channel(1);
+        let reader = log.subscribe();
+        let forward = tokio::spawn(async move { reader.forward(&tx, |chunk| chunk.data).await });
+        assert_eq!(rx.recv().await.unwrap(), b"hello");
+        // The log stays open and quiet, so only the receiver going away can
+        // end the forwarding.
+        drop(rx);
+        tokio::time::timeout(Duration::from_secs(1), forward)
+            .await
+            .expect("forwarding went on after the receiver went away")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn forwarding_ends_with_the_log() {
+        let log = Arc::new(OutputLog::new());
+        let (tx, mut rx) = mpsc::channel(8);
+        let reader = log.subscribe();
+        let forward = tokio::spawn(async move { reader.forward(&tx, |chunk| chunk.data).await });
+        log.append(Stdout, b"a");
+        log.append(Stderr, b"b");
+        log.close();
+        forward.await.unwrap();
+        let mut received = Vec::new();
+        while let Some(data) = rx.recv().await {
+            received.extend(data);
+        }
+        assert_eq!(received, b"ab");
+    }
+
+    #[tokio::test]
+    async fn interleaved_streams_keep_their_write_order() {
+        let writes: [(OutputStream, &[u8]); 5] = [
+            (Stdout, b"1"),
+            (Stderr, b"2"),
+            (Stdout, b"3"),
+            (Stdout, b"4"),
+            (Stderr, b"5"),
+        ];
+        for log in written(&writes) {
+            let chunks = read_all(log.subscribe()).await;
+            let order: Vec<_> = chunks
+                .iter()
+                .flat_map(|chunk| chunk.data.iter().map(move |&byte| (chunk.stream, byte)))
+                .collect();
+            let expected: Vec<_> = writes
+                .iter()
+                .map(|(stream, data)| (*stream, data[0]))
+                .collect();
+            assert_eq!(order, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn filtered_readers_report_offsets_in_the_combined_output() {
+        let writes: [(OutputStream, &[u8]); 4] = [
+            (Stdout, b"out1 "),
+            (Stderr, b"err1 "),
+            (Stdout, b"out2 "),
+            (Stderr, b"err2"),
+        ];
+        for log in written(&writes) {
+            let stdout = read_all(log.subscribe().with_stream(Some(Stdout))).await;
+            let stderr = read_all(log.subscribe().with_stream(Some(Stderr))).await;
+            assert_eq!(data(&stdout), b"out1 out2 ");
+            assert_eq!(data(&stderr), b"err1 err2");
+            let offsets = |chunks: &[Chunk]| {
+                chunks
+                    .iter()
+                    .map(|chunk| (chunk.stream, chunk.offset))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(offsets(&stdout), [(Stdout, 0), (Stdout, 10)]);
+            assert_eq!(offsets(&stderr), [(Stderr, 5), (Stderr, 15)]);
+
+            // An offset taken from a filtered chunk resumes the same stream
+            // right there.
+            let resumed = read_all(log.subscribe().with_stream(Some(Stderr)).with_offset(15)).await;
+            assert_eq!(data(&resumed), b"err2");
+            // Starting inside a segment of the other stream skips the rest
+            // of it.
+            let resumed = read_all(log.subscribe().with_stream(Some(Stderr)).with_offset(2)).await;
+            assert_eq!(offsets(&resumed), [(Stderr, 5), (Stderr, 15)]);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Retry backoff.
+//!
+//! A failed job is re-run after a delay that grows exponentially with the
+//! number of retries so far, capped at a maximum and spread by random jitter
+//! so that jobs failing together do not come back together.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay, before jitter.
+    pub max_backoff: Duration,
+    /// Factor applied to the delay after every retry.
+    pub multiplier: f64,
+    /// Relative jitter in `[0, 1]`: the delay is scaled by a random factor in
+    /// `[1 - jitter, 1 + jitter]`.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry`, counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let base = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        let factor = if self.jitter > 0.0 {
+            rand::rng().random_range(1.0 - self.jitter..=1.0 + self.jitter)
+        } else {
+            1.0
+        };
+        Duration::from_secs_f64((base * factor).max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steady(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            jitter,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn backoff_grows_by_the_multiplier() {
+        let policy = steady(0.0);
+        let delays: Vec<_> = (1..=5).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16].map(Duration::from_secs));
+        // Retries count from 1; 0 is treated like the first.
+        assert_eq!(policy.backoff(0), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = steady(0.0);
+        assert_eq!(policy.backoff(7), Duration::from_secs(60));
+        assert_eq!(policy.backoff(100), Duration::from_secs(60));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn jitter_stays_within_its_bounds() {
+        let policy = steady(0.2);
+        for retry in [1, 3, 100] {
+            let base = steady(0.0).backoff(retry).as_secs_f64();
+            for _ in 0..100 {
+                let delay = policy.backoff(retry).as_secs_f64();
+                // Durations are rounded to the nanosecond.
+                assert!(
+                    (base * 0.8 - 1e-9..=base * 1.2 + 1e-9).contains(&delay),
+                    "{} outside {}±20%",
+                    delay,
+                    base
+                );
+            }
+        }
+    }
+}
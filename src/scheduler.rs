@@ -0,0 +1,463 @@
+//! Priority scheduling of job attempts.
+//!
+//! At most `slots` attempts run at a time. Attempts waiting for a slot are
+//! dispatched by priority, then in submission order. With aging enabled, an
+//! attempt gains one priority level for every `aging` interval it has waited,
+//! so that a steady stream of high-priority jobs cannot starve the others.
+//!
+//! Attempts also reserve their job's [`Quota`] against the host capacity for
+//! as long as they run. The attempt first in line waits until its quota fits
+//! into what is left; attempts behind it are not started ahead of it, so a
+//! large job is not starved by smaller ones. A zero quota or capacity leaves
+//! that dimension unaccounted.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use crate::cgroup::Quota;
+
+/// Lowest priority a job may have.
+pub const MIN_PRIORITY: i32 = -10;
+/// Highest priority a job may have.
+pub const MAX_PRIORITY: i32 = 10;
+
+/// Number of slots of [`Scheduler::default`].
+pub const DEFAULT_SLOTS: usize = 4;
+/// Aging interval of [`Scheduler::default`].
+pub const DEFAULT_AGING: Duration = Duration::from_secs(30);
+
+/// A quota that can never fit into the host capacity.
+#[derive(Debug, Error)]
+#[error("{resource} quota of {requested} {unit} exceeds the host capacity of {capacity} {unit}")]
+pub struct CapacityError {
+    pub resource: &'static str,
+    pub unit: &'static str,
+    pub requested: u32,
+    pub capacity: u32,
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    slots: usize,
+    /// `None` disables aging.
+    aging: Option<Duration>,
+    capacity: Quota,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    running: usize,
+    reserved: Quota,
+    queue: Vec<Waiter>,
+    next_seq: u64,
+}
+
+impl State {
+    fn fits(&self, quota: &Quota, capacity: &Quota) -> bool {
+        let fits = |reserved: u32, requested: u32, capacity: u32| {
+            capacity == 0 || reserved as u64 + requested as u64 <= capacity as u64
+        };
+        fits(self.reserved.cpu, quota.cpu, capacity.cpu)
+            && fits(self.reserved.memory, quota.memory, capacity.memory)
+            && fits(self.reserved.io, quota.io, capacity.io)
+    }
+
+    fn reserve(&mut self, quota: &Quota) {
+        self.running += 1;
+        self.reserved.cpu = self.reserved.cpu.saturating_add(quota.cpu);
+        self.reserved.memory = self.reserved.memory.saturating_add(quota.memory);
+        self.reserved.io = self.reserved.io.saturating_add(quota.io);
+    }
+
+    fn release(&mut self, quota: &Quota) {
+        self.running -= 1;
+        self.reserved.cpu = self.reserved.cpu.saturating_sub(quota.cpu);
+        self.reserved.memory = self.reserved.memory.saturating_sub(quota.memory);
+        self.reserved.io = self.reserved.io.saturating_sub(quota.io);
+    }
+}
+
+/// `(resource, unit, value of quota, value of capacity)` for every dimension.
+fn dimensions(
+    quota: &Quota,
+    capacity: &Quota,
+) -> impl Iterator<Item = (&'static str, &'static str, u32, u32)> {
+    [
+        ("cpu", "cores", quota.cpu, capacity.cpu),
+        ("memory", "MB", quota.memory, capacity.memory),
+        ("io", "MB/s", quota.io, capacity.io),
+    ]
+    .into_iter()
+}
+
+#[derive(Debug)]
+struct Waiter {
+    job_id: String,
+    priority: i32,
+    quota: Quota,
+    queued_at: Instant,
+    seq: u64,
+    ready: oneshot::Sender<()>,
+}
+
+impl Waiter {
+    fn effective_priority(&self, aging: Option<Duration>, now: Instant) -> i64 {
+        let boost = match aging {
+            Some(aging) if !aging.is_zero() => {
+                (now.duration_since(self.queued_at).as_nanos() / aging.as_nanos()) as i64
+            }
+            _ => 0,
+        };
+        self.priority as i64 + boost
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOTS, Some(DEFAULT_AGING))
+    }
+}
+
+impl Scheduler {
+    /// A scheduler running up to `slots` attempts at a time (at least one),
+    /// without capacity accounting.
+    pub fn new(slots: usize, aging: Option<Duration>) -> Self {
+        Self {
+            slots: slots.max(1),
+            aging,
+            capacity: Quota::default(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Admit attempts only while the sum of their quotas fits into `capacity`.
+    pub fn with_capacity(mut self, capacity: Quota) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Check that `quota` fits into the host capacity at all.
+    pub fn check_capacity(&self, quota: &Quota) -> Result<(), CapacityError> {
+        match dimensions(quota, &self.capacity)
+            .find(|&(_, _, requested, capacity)| capacity != 0 && requested > capacity)
+        {
+            Some((resource, unit, requested, capacity)) => Err(CapacityError {
+                resource,
+                unit,
+                requested,
+                capacity,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait for a free slot, and room for `quota`, for an attempt of
+    /// `job_id`. Both are held until the returned permit is dropped; dropping
+    /// the future while it waits leaves the queue.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        job_id: &str,
+        priority: i32,
+        quota: Quota,
+    ) -> SlotPermit {
+        let (ready, rx) = oneshot::channel();
+        let seq = {
+            let mut state = self.state.lock().unwrap();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.queue.push(Waiter {
+                job_id: job_id.to_string(),
+                priority,
+                quota,
+                queued_at: Instant::now(),
+                seq,
+                ready,
+            });
+            self.dispatch(&mut state);
+            seq
+        };
+
+        let mut waiting = Waiting {
+            scheduler: self,
+            seq,
+            quota,
+            done: false,
+        };
+        // The sender is only dropped once it has fired.
+        let _ = rx.await;
+        waiting.done = true;
+        SlotPermit {
+            scheduler: self.clone(),
+            quota,
+        }
+    }
+
+    /// 1-based position of `job_id` in the dispatch order, if it is waiting.
+    pub fn queue_position(&self, job_id: &str) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut order: Vec<&Waiter> = state.queue.iter().collect();
+        order.sort_by_key(|waiter| self.sort_key(waiter, now));
+        order
+            .iter()
+            .position(|waiter| waiter.job_id == job_id)
+            .map(|index| index + 1)
+    }
+
+    /// Number of attempts waiting for a slot.
+    pub fn queued_count(&self) -> usize {
+        self.state.lock().unwrap().queue.len()
+    }
+
+    fn sort_key(&self, waiter: &Waiter, now: Instant) -> (i64, u64) {
+        (-waiter.effective_priority(self.aging, now), waiter.seq)
+    }
+
+    /// Hand free slots to the best waiters, as long as the first in line fits.
+    fn dispatch(&self, state: &mut State) {
+        let now = Instant::now();
+        while state.running < self.slots {
+            let Some(index) = state
+                .queue
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, waiter)| self.sort_key(waiter, now))
+                .map(|(index, _)| index)
+            else {
+                break;
+            };
+            if !state.fits(&state.queue[index].quota, &self.capacity) {
+                break;
+            }
+            let waiter = state.queue.remove(index);
+            debug!(job_id = %waiter.job_id, priority = waiter.priority, quota = ?waiter.quota, "dispatching job");
+            state.reserve(&waiter.quota);
+            // The receiver outlives the queue entry, see `Waiting`.
+            let _ = waiter.ready.send(());
+        }
+    }
+
+    fn release(&self, quota: &Quota) {
+        let mut state = self.state.lock().unwrap();
+        state.release(quota);
+        self.dispatch(&mut state);
+    }
+}
+
+/// Cleans up after an [`Scheduler::acquire`] future dropped while waiting.
+struct Waiting<'a> {
+    scheduler: &'a Scheduler,
+    seq: u64,
+    quota: Quota,
+    done: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut state = self.scheduler.state.lock().unwrap();
+        match state.queue.iter().position(|waiter| waiter.seq == self.seq) {
+            Some(index) => {
+                state.queue.remove(index);
+            }
+            // Dispatched but never picked up: hand the slot on.
+            None => {
+                state.release(&self.quota);
+                self.scheduler.dispatch(&mut state);
+            }
+        }
+    }
+}
+
+/// A running slot and its reserved quota; dropping it frees both for the
+/// next waiter.
+#[derive(Debug)]
+pub struct SlotPermit {
+    scheduler: Arc<Scheduler>,
+    quota: Quota,
+}
+
+impl Drop for SlotPermit {
+    fn drop(&mut self) {
+        self.scheduler.release(&self.quota);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::sync::mpsc;
+    use tokio::task::JoinHandle;
+
+    /// Queue an attempt of `job_id`, report it on `started` once it gets a
+    /// slot and give the slot back right away.
+    fn queue(
+        scheduler: &Arc<Scheduler>,
+        job_id: &'static str,
+        priority: i32,
+        started: &mpsc::UnboundedSender<&'static str>,
+    ) -> JoinHandle<()> {
+        let scheduler = scheduler.clone();
+        let started = started.clone();
+        tokio::spawn(async move {
+            let _permit = scheduler.acquire(job_id, priority, Quota::default()).await;
+            started.send(job_id).unwrap();
+        })
+    }
+
+    /// Let spawned tasks run until `count` attempts are waiting.
+    async fn until_queued(scheduler: &Scheduler, count: usize) {
+        while scheduler.queued_count() != count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn collect(
+        mut started: mpsc::UnboundedReceiver<&'static str>,
+        count: usize,
+    ) -> Vec<&'static str> {
+        let mut order = Vec::new();
+        while order.len() < count {
+            order.push(started.recv().await.unwrap());
+        }
+        order
+    }
+
+    #[tokio::test]
+    async fn dispatches_by_priority_then_in_submission_order() {
+        let scheduler = Arc::new(Scheduler::new(1, None));
+        let busy = scheduler.acquire("busy", 0, Quota::default()).await;
+        let (tx, rx) = mpsc::unbounded_channel();
+        for (job_id, priority) in [("a", 0), ("b", 5), ("c", 0), ("d", 5), ("e", -3)] {
+            queue(&scheduler, job_id, priority, &tx);
+            until_queued(&scheduler, scheduler.queued_count() + 1).await;
+        }
+
+        let positions: Vec<_> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|job_id| scheduler.queue_position(job_id))
+            .collect();
+        assert_eq!(positions, [Some(3), Some(1), Some(4), Some(2), Some(5)]);
+        assert_eq!(scheduler.queue_position("busy"), None);
+
+        drop(busy);
+        assert_eq!(collect(rx, 5).await, ["b", "d", "a", "c", "e"]);
+        assert_eq!(scheduler.queued_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn aging_lets_old_attempts_overtake_higher_priorities() {
+        let aging = Duration::from_millis(20);
+        let scheduler = Arc::new(Scheduler::new(1, Some(aging)));
+        let busy = scheduler.acquire("busy", 0, Quota::default()).await;
+        let (tx, rx) = mpsc::unbounded_channel();
+        queue(&scheduler, "old", 0, &tx);
+        until_queued(&scheduler, 1).await;
+        // Three aging intervals lift `old` above a fresh priority of 2.
+        tokio::time::sleep(aging * 3).await;
+        queue(&scheduler, "new", 2, &tx);
+        until_queued(&scheduler, 2).await;
+
+        assert_eq!(scheduler.queue_position("old"), Some(1));
+        assert_eq!(scheduler.queue_position("new"), Some(2));
+        drop(busy);
+        assert_eq!(collect(rx, 2).await, ["old", "new"]);
+    }
+
+    #[tokio::test]
+    async fn without_aging_priority_always_wins() {
+        let scheduler = Arc::new(Scheduler::new(1, None));
+        let busy = scheduler.acquire("busy", 0, Quota::default()).await;
+        let (tx, rx) = mpsc::unbounded_channel();
+        queue(&scheduler, "old", 0, &tx);
+        until_queued(&scheduler, 1).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        queue(&scheduler, "new", 1, &tx);
+        until_queued(&scheduler, 2).await;
+
+        drop(busy);
+        assert_eq!(collect(rx, 2).await, ["new", "old"]);
+    }
+
+    #[tokio::test]
+    async fn a_dispatched_attempt_dropped_before_starting_hands_its_slot_on() {
+        let scheduler = Arc::new(Scheduler::new(1, None));
+        let busy = scheduler.acquire("busy", 0, Quota::default()).await;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let first = queue(&scheduler, "first", 1, &tx);
+        until_queued(&scheduler, 1).await;
+        queue(&scheduler, "second", 0, &tx);
+        until_queued(&scheduler, 2).await;
+
+        // Releasing the slot dispatches `first`, which has not run yet when
+        // its task is aborted.
+        drop(busy);
+        assert_eq!(scheduler.queue_position("first"), None);
+        first.abort();
+        assert!(first.await.unwrap_err().is_cancelled());
+
+        assert_eq!(collect(rx, 1).await, ["second"]);
+        assert_eq!(scheduler.queued_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn an_attempt_dropped_while_queued_leaves_the_queue() {
+        let scheduler = Arc::new(Scheduler::new(1, None));
+        let busy = scheduler.acquire("busy", 0, Quota::default()).await;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let waiting = queue(&scheduler, "gone", 5, &tx);
+        until_queued(&scheduler, 1).await;
+        queue(&scheduler, "kept", 0, &tx);
+        until_queued(&scheduler, 2).await;
+
+        waiting.abort();
+        until_queued(&scheduler, 1).await;
+        assert_eq!(scheduler.queue_position("kept"), Some(1));
+        drop(busy);
+        assert_eq!(collect(rx, 1).await, ["kept"]);
+    }
+
+    #[tokio::test]
+    async fn the_first_in_line_waits_for_its_quota_to_fit() {
+        let capacity = Quota {
+            cpu: 4,
+            memory: 0,
+            io: 0,
+        };
+        let scheduler = Arc::new(Scheduler::new(4, None).with_capacity(capacity));
+        let cpus = |cpu| Quota {
+            cpu,
+            memory: 0,
+            io: 0,
+        };
+        assert!(scheduler.check_capacity(&cpus(5)).is_err());
+
+        let three = scheduler.acquire("three", 0, cpus(3)).await;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let big = {
+            let scheduler = scheduler.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire("big", 1, cpus(2)).await;
+                tx.send("big").unwrap();
+            })
+        };
+        until_queued(&scheduler, 1).await;
+        // Would fit, but must not start ahead of `big`.
+        queue(&scheduler, "small", 0, &tx);
+        tokio::task::yield_now().await;
+        assert_eq!(scheduler.queue_position("small"), Some(2));
+
+        drop(three);
+        assert_eq!(collect(rx, 2).await, ["big", "small"]);
+        big.await.unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+//! Job lifecycle.
+//!
+//! A job's [`JobStatus`] only ever changes through [`JobStatus::apply`], which
+//! checks every [`JobEvent`] against the current [`JobState`] and rejects the
+//! transitions the lifecycle below does not allow:
+//!
+//! ```text
+//! Queued ──Start──▶ Running ──Exit──▶ Succeeded | Failed
+//!    │                 │ ──Stop──▶ Stopped
+//!    │                 │ ──TimeOut──▶ TimedOut
+//!    │                 ▼
+//!    │   Failed | TimedOut ──Retry──▶ Retrying ──Start──▶ Running
+//!    └──── Queued | Retrying ──Cancel──▶ Stopped
+//!
+//! Queued | Running | Retrying ──Lose──▶ Lost
+//! ```
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobState {
+    /// Accepted, waiting for its first attempt to start.
+    Queued,
+    Running,
+    /// The process exited with status 0.
+    Succeeded,
+    /// The process exited with a non-zero status or was killed by a signal.
+    Failed,
+    /// Ended by a stop request.
+    Stopped,
+    /// Killed for running longer than its timeout.
+    TimedOut,
+    /// The last attempt failed; waiting for the next one to start.
+    Retrying,
+    /// The server went down before the job finished.
+    Lost,
+}
+
+impl JobState {
+    /// Whether the job has reached a state it can only leave by being retried.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded
+                | JobState::Failed
+                | JobState::Stopped
+                | JobState::TimedOut
+                | JobState::Lost
+        )
+    }
+}
+
+/// How a job that was asked to stop came to an end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopOutcome {
+    /// Exited with a status code within the grace period.
+    ExitedCleanly,
+    /// Died from SIGTERM within the grace period.
+    Terminated,
+    /// Outlived the grace period and was killed with SIGKILL.
+    Killed,
+    /// Stopped while no attempt was running.
+    Cancelled,
+}
+
+/// How a job's process ended. At most one of the fields is set; both are
+/// `None` when the exit status could not be collected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessExit {
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+impl ProcessExit {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl From<ExitStatus> for ProcessExit {
+    fn from(status: ExitStatus) -> Self {
+        Self {
+            code: status.code(),
+            signal: status.signal(),
+        }
+    }
+}
+
+/// Something that happened to a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobEvent {
+    /// A new attempt's process was spawned.
+    Start,
+    /// The process exited on its own.
+    Exit(ProcessExit),
+    /// The process exited after a stop request.
+    Stop {
+        outcome: StopOutcome,
+        exit: ProcessExit,
+    },
+    /// The process was killed for exceeding its timeout.
+    TimeOut(ProcessExit),
+    /// Another attempt will be made.
+    Retry,
+    /// Stopped before an attempt was running.
+    Cancel,
+    /// Found unfinished when recovering from a server restart.
+    Lose,
+}
+
+#[derive(Debug, Error)]
+#[error("illegal job transition: {event:?} in state {state:?}")]
+pub struct TransitionError {
+    pub state: JobState,
+    pub event: JobEvent,
+}
+
+/// Summary of one finished attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempt {
+    /// Attempt number, counting from 1.
+    pub number: u32,
+    /// State the job entered when the attempt ended.
+    pub state: JobState,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub state: JobState,
+    /// Exit code of the last attempt, if it exited normally.
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the last attempt.
+    pub signal: Option<i32>,
+    /// Set when the job was ended by a stop request.
+    pub stop_outcome: Option<StopOutcome>,
+    pub created_at: SystemTime,
+    /// Start of the last attempt.
+    pub started_at: Option<SystemTime>,
+    /// End of the last attempt.
+    pub finished_at: Option<SystemTime>,
+    /// Number of attempts started so far; 0 while queued.
+    pub attempt: u32,
+    /// Every finished attempt, oldest first.
+    pub attempts: Vec<Attempt>,
+}
+
+impl Default for JobStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JobStatus {
+    /// Status of a job that was just accepted.
+    pub fn new() -> Self {
+        Self {
+            state: JobState::Queued,
+            exit_code: None,
+            signal: None,
+            stop_outcome: None,
+            created_at: SystemTime::now(),
+            started_at: None,
+            finished_at: None,
+            attempt: 0,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Apply `event`, or leave the status untouched if the current state
+    /// does not allow it.
+    pub fn apply(&mut self, event: JobEvent) -> Result<(), TransitionError> {
+        use JobState::*;
+
+        let now = SystemTime::now();
+        match (self.state, event) {
+            (Queued | Retrying, JobEvent::Start) => {
+                self.state = Running;
+                self.attempt += 1;
+                self.started_at = Some(now);
+                self.finished_at = None;
+                self.exit_code = None;
+                self.signal = None;
+            }
+            (Running, JobEvent::Exit(exit)) => {
+                self.state = if exit.success() { Succeeded } else { Failed };
+                self.finish(exit, now);
+            }
+            (Running, JobEvent::Stop { outcome, exit }) => {
+                self.state = Stopped;
+                self.stop_outcome = Some(outcome);
+                self.finish(exit, now);
+            }
+            (Running, JobEvent::TimeOut(exit)) => {
+                self.state = TimedOut;
+                self.finish(exit, now);
+            }
+            (Failed | TimedOut, JobEvent::Retry) => self.state = Retrying,
+            (Queued | Retrying, JobEvent::Cancel) => {
+                self.state = Stopped;
+                self.stop_outcome = Some(StopOutcome::Cancelled);
+                self.finished_at = Some(now);
+            }
+            (Running, JobEvent::Lose) => {
+                self.state = Lost;
+                self.finish(ProcessExit::default(), now);
+            }
+            (Queued | Retrying, JobEvent::Lose) => {
+                self.state = Lost;
+                self.finished_at = Some(now);
+            }
+            (state, event) => return Err(TransitionError { state, event }),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, exit: ProcessExit, now: SystemTime) {
+        self.exit_code = exit.code;
+        self.signal = exit.signal;
+        self.finished_at = Some(now);
+        self.attempts.push(Attempt {
+            number: self.attempt,
+            state: self.state,
+            exit_code: exit.code,
+            signal: exit.signal,
+            started_at: self.started_at.unwrap_or(now),
+            finished_at: now,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_STATES: [JobState; 8] = [
+        JobState::Queued,
+        JobState::Running,
+        JobState::Succeeded,
+        JobState::Failed,
+        JobState::Stopped,
+        JobState::TimedOut,
+        JobState::Retrying,
+        JobState::Lost,
+    ];
+
+    const SUCCESS: ProcessExit = ProcessExit {
+        code: Some(0),
+        signal: None,
+    };
+    const FAILURE: ProcessExit = ProcessExit {
+        code: Some(1),
+        signal: None,
+    };
+
+    fn all_events() -> [JobEvent; 8] {
+        [
+            JobEvent::Start,
+            JobEvent::Exit(SUCCESS),
+            JobEvent::Exit(FAILURE),
+            JobEvent::Stop {
+                outcome: StopOutcome::Terminated,
+                exit: ProcessExit {
+                    code: None,
+                    signal: Some(15),
+                },
+            },
+            JobEvent::TimeOut(FAILURE),
+            JobEvent::Retry,
+            JobEvent::Cancel,
+            JobEvent::Lose,
+        ]
+    }
+
+    /// The state `event` leads to from `state`, or `None` if it is illegal.
+    fn expected(state: JobState, event: JobEvent) -> Option<JobState> {
+        use JobState::*;
+
+        match (state, event) {
+            (Queued | Retrying, JobEvent::Start) => Some(Running),
+            (Running, JobEvent::Exit(exit)) if exit.success() => Some(Succeeded),
+            (Running, JobEvent::Exit(_)) => Some(Failed),
+            (Running, JobEvent::Stop { .. }) => Some(Stopped),
+            (Running, JobEvent::TimeOut(_)) => Some(TimedOut),
+            (Failed | TimedOut, JobEvent::Retry) => Some(Retrying),
+            (Queued | Retrying, JobEvent::Cancel) => Some(Stopped),
+            (Queued | Running | Retrying, JobEvent::Lose) => Some(Lost),
+            _ => None,
+        }
+    }
+
+    fn status_in(state: JobState) -> JobStatus {
+        JobStatus {
+            state,
+            ..JobStatus::new()
+        }
+    }
+
+    #[test]
+    fn allowed_transitions_reach_the_expected_state() {
+        for state in ALL_STATES {
+            for event in all_events() {
+                let Some(next) = expected(state, event) else {
+                    continue;
+                };
+                let mut status = status_in(state);
+                status
+                    .apply(event)
+                    .unwrap_or_else(|e| panic!("{:?} in {:?} was rejected: {}", event, state, e));
+                assert_eq!(status.state, next, "{:?} in {:?}", event, state);
+            }
+        }
+    }
+
+    #[test]
+    fn illegal_transitions_leave_the_status_untouched() {
+        for state in ALL_STATES {
+            for event in all_events() {
+                if expected(state, event).is_some() {
+                    continue;
+                }
+                let mut status = status_in(state);
+                let before = status.clone();
+                let err = status.apply(event).unwrap_err();
+                assert_eq!((err.state, err.event), (state, event));
+                assert_eq!(status, before, "{:?} in {:?}", event, state);
+            }
+        }
+    }
+
+    #[test]
+    fn finished_states_only_leave_through_retry() {
+        for state in ALL_STATES.into_iter().filter(JobState::is_finished) {
+            for event in all_events() {
+                if let Some(next) = expected(state, event) {
+                    assert_eq!((event, next), (JobEvent::Retry, JobState::Retrying));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn attempts_are_counted_and_recorded() {
+        let mut status = JobStatus::new();
+        status.apply(JobEvent::Start).unwrap();
+        status.apply(JobEvent::Exit(FAILURE)).unwrap();
+        status.apply(JobEvent::Retry).unwrap();
+        assert_eq!(status.exit_code, Some(1));
+
+        status.apply(JobEvent::Start).unwrap();
+        assert_eq!(status.attempt, 2);
+        assert_eq!(status.exit_code, None);
+        assert_eq!(status.finished_at, None);
+        status.apply(JobEvent::Exit(SUCCESS)).unwrap();
+
+        let attempts: Vec<_> = status
+            .attempts
+            .iter()
+            .map(|attempt| (attempt.number, attempt.state, attempt.exit_code))
+            .collect();
+        assert_eq!(
+            attempts,
+            [
+                (1, JobState::Failed, Some(1)),
+                (2, JobState::Succeeded, Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_and_cancel_record_their_outcome() {
+        let mut running = status_in(JobState::Running);
+        running
+            .apply(JobEvent::Stop {
+                outcome: StopOutcome::Killed,
+                exit: ProcessExit {
+                    code: None,
+                    signal: Some(9),
+                },
+            })
+            .unwrap();
+        assert_eq!(running.stop_outcome, Some(StopOutcome::Killed));
+        assert_eq!(running.signal, Some(9));
+
+        let mut queued = JobStatus::new();
+        queued.apply(JobEvent::Cancel).unwrap();
+        assert_eq!(queued.stop_outcome, Some(StopOutcome::Cancelled));
+        assert!(queued.attempts.is_empty());
+        assert!(queued.finished_at.is_some());
+    }
+}
@@ -0,0 +1,648 @@
+//! Persistent job store.
+//!
+//! The [`Worker`](crate::worker::Worker) records every job it accepts, each
+//! status change and all output in a [`JobStore`], and reloads them when it
+//! starts so that jobs stay queryable across server restarts.
+//!
+//! [`MemoryStore`] keeps jobs in memory and spools output to unlinked files
+//! in a temporary directory, so all of it is lost with the process.
+//! [`DiskStore`] keeps it in a directory, spooling output to a file per
+//! attempt:
+//!
+//! ```text
+//! <dir>/jobs.jsonl                       append-only log of jobs and status changes
+//! <dir>/output/<job id>/<attempt>        output of every attempt
+//! <dir>/output/<job id>/<attempt>.index  stream and time of every part of it
+//! ```
+//!
+//! The log is compacted to one line per job whenever the store is opened.
+//! Entries are flushed to disk by a writer thread, off the async runtime.
+//! Jobs carry their environment and input, so everything in the directory is
+//! only accessible to the server's user.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, DirBuilder, File, OpenOptions, Permissions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::output::OutputLog;
+use crate::state::JobStatus;
+use crate::worker::{AttemptProcess, JobId, JobSpec};
+
+const LOG_FILE: &str = "jobs.jsonl";
+const OUTPUT_DIR: &str = "output";
+/// Permissions of every directory and file of a [`DiskStore`].
+const DIR_MODE: u32 = 0o700;
+const FILE_MODE: u32 = 0o600;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("job store operation on {path:?} failed: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to encode job record: {0}")]
+    Encode(#[source] serde_json::Error),
+}
+
+impl StoreError {
+    fn io(path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// A job as stored: what to run and how far it got.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: JobId,
+    pub spec: JobSpec,
+    pub status: JobStatus,
+    /// Process of the attempt that was running when the record was last
+    /// written, so that a restarted server can kill what is left of it.
+    #[serde(default)]
+    pub process: Option<AttemptProcess>,
+}
+
+pub trait JobStore: fmt::Debug + Send + Sync {
+    /// Record a newly accepted job.
+    fn insert(&self, record: &JobRecord) -> Result<(), StoreError>;
+
+    /// Record the latest status of job `id`.
+    fn update_status(&self, id: &str, status: &JobStatus) -> Result<(), StoreError>;
+
+    /// Record the process of the running attempt of job `id`, or `None` once
+    /// it is gone.
+    fn update_process(&self, id: &str, process: Option<&AttemptProcess>) -> Result<(), StoreError>;
+
+    /// A new, empty output log for attempt `attempt` (counting from 1) of
+    /// job `id`, truncated at `max_size` bytes.
+    fn create_output(
+        &self,
+        id: &str,
+        attempt: u32,
+        max_size: Option<u64>,
+    ) -> Result<Arc<OutputLog>, StoreError>;
+
+    /// The output log of attempt `attempt` of job `id`, as left by the
+    /// previous server; discarded if there is none.
+    fn open_output(&self, id: &str, attempt: u32) -> Result<Arc<OutputLog>, StoreError>;
+
+    /// Delete the output of every attempt of job `id`.
+    fn remove_output(&self, id: &str) -> Result<(), StoreError>;
+
+    /// Every stored job with its latest status, in submission order.
+    fn load(&self) -> Result<Vec<JobRecord>, StoreError>;
+}
+
+/// A [`JobStore`] that keeps jobs in memory, and output in files that go
+/// away with the last handle to them.
+#[derive(Debug)]
+pub struct MemoryStore {
+    jobs: Mutex<Vec<JobRecord>>,
+    outputs: Mutex<HashMap<(JobId, u32), Arc<OutputLog>>>,
+    spool_dir: PathBuf,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self {
+            jobs: Mutex::default(),
+            outputs: Mutex::default(),
+            spool_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spool output to `dir` instead of the system's temporary directory.
+    pub fn with_spool_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.spool_dir = dir.into();
+        self
+    }
+}
+
+impl JobStore for MemoryStore {
+    fn insert(&self, record: &JobRecord) -> Result<(), StoreError> {
+        self.jobs.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+
+    fn update_status(&self, id: &str, status: &JobStatus) -> Result<(), StoreError> {
+        if let Some(record) = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|record| record.id == id)
+        {
+            record.status = status.clone();
+        }
+        Ok(())
+    }
+
+    fn update_process(&self, id: &str, process: Option<&AttemptProcess>) -> Result<(), StoreError> {
+        if let Some(record) = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|record| record.id == id)
+        {
+            record.process = process.cloned();
+        }
+        Ok(())
+    }
+
+    fn create_output(
+        &self,
+        id: &str,
+        attempt: u32,
+        max_size: Option<u64>,
+    ) -> Result<Arc<OutputLog>, StoreError> {
+        let data = unlinked_file(&self.spool_dir)?;
+        let output = Arc::new(OutputLog::spooled(data, None).with_max_size(max_size));
+        self.outputs
+            .lock()
+            .unwrap()
+            .insert((id.to_string(), attempt), output.clone());
+        Ok(output)
+    }
+
+    fn open_output(&self, id: &str, attempt: u32) -> Result<Arc<OutputLog>, StoreError> {
+        Ok(self
+            .outputs
+            .lock()
+            .unwrap()
+            .get(&(id.to_string(), attempt))
+            .cloned()
+            .unwrap_or_else(|| Arc::new(OutputLog::discarded())))
+    }
+
+    fn remove_output(&self, id: &str) -> Result<(), StoreError> {
+        self.outputs
+            .lock()
+            .unwrap()
+            .retain(|(job_id, _), _| job_id != id);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<JobRecord>, StoreError> {
+        Ok(self.jobs.lock().unwrap().clone())
+    }
+}
+
+/// One line of the job log.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Job(Box<JobRecord>),
+    Status {
+        id: JobId,
+        status: JobStatus,
+    },
+    Process {
+        id: JobId,
+        process: Option<AttemptProcess>,
+    },
+}
+
+/// A [`JobStore`] that keeps everything in a directory.
+#[derive(Debug)]
+pub struct DiskStore {
+    dir: PathBuf,
+    log: Mutex<File>,
+    // Wakes up the writer thread to flush the log; closed on drop.
+    sync: Option<mpsc::Sender<()>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl DiskStore {
+    /// Open the store in `dir`, creating it if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let dir = dir.into();
+        let output_dir = dir.join(OUTPUT_DIR);
+        create_private_dir(&output_dir)?;
+        // Also lock down directories left behind with looser permissions.
+        for path in [&dir, &output_dir] {
+            fs::set_permissions(path, Permissions::from_mode(DIR_MODE))
+                .map_err(|e| StoreError::io(path, e))?;
+        }
+
+        // Rewrite the log with one entry per job, then swap it in.
+        let path = dir.join(LOG_FILE);
+        let records = replay(&path)?;
+        let compacted = dir.join(format!("{}.tmp", LOG_FILE));
+        {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .mode(FILE_MODE)
+                .open(&compacted)
+                .map_err(|e| StoreError::io(&compacted, e))?;
+            // `mode` only applies if the file did not exist yet.
+            file.set_permissions(Permissions::from_mode(FILE_MODE))
+                .map_err(|e| StoreError::io(&compacted, e))?;
+            for record in records {
+                write_entry(&mut file, &compacted, &Entry::Job(Box::new(record)))?;
+            }
+            file.sync_all().map_err(|e| StoreError::io(&compacted, e))?;
+        }
+        fs::rename(&compacted, &path).map_err(|e| StoreError::io(&path, e))?;
+
+        let log = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| StoreError::io(&path, e))?;
+        let synced = log.try_clone().map_err(|e| StoreError::io(&path, e))?;
+        let (sync, requests) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("job-log-writer".to_string())
+            .spawn(move || sync_log(synced, &path, requests))
+            .map_err(|e| StoreError::io(&dir, e))?;
+        debug!(?dir, "opened job store");
+        Ok(Self {
+            dir,
+            log: Mutex::new(log),
+            sync: Some(sync),
+            writer: Some(writer),
+        })
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.join(LOG_FILE)
+    }
+
+    fn output_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(OUTPUT_DIR).join(id)
+    }
+
+    fn output_path(&self, id: &str, attempt: u32) -> PathBuf {
+        self.output_dir(id).join(attempt.to_string())
+    }
+
+    fn index_path(&self, id: &str, attempt: u32) -> PathBuf {
+        self.output_dir(id).join(format!("{}.index", attempt))
+    }
+
+    /// Append `entry` to the log, leaving it to the writer thread to flush
+    /// it to disk.
+    fn append(&self, entry: &Entry) -> Result<(), StoreError> {
+        let mut line = serde_json::to_vec(entry).map_err(StoreError::Encode)?;
+        line.push(b'\n');
+        self.log
+            .lock()
+            .unwrap()
+            .write_all(&line)
+            .map_err(|e| StoreError::io(&self.log_path(), e))?;
+        if let Some(sync) = &self.sync {
+            let _ = sync.send(());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        // The writer flushes what is left once it runs out of requests.
+        drop(self.sync.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl JobStore for DiskStore {
+    fn insert(&self, record: &JobRecord) -> Result<(), StoreError> {
+        self.append(&Entry::Job(Box::new(record.clone())))
+    }
+
+    fn update_status(&self, id: &str, status: &JobStatus) -> Result<(), StoreError> {
+        self.append(&Entry::Status {
+            id: id.to_string(),
+            status: status.clone(),
+        })
+    }
+
+    fn update_process(&self, id: &str, process: Option<&AttemptProcess>) -> Result<(), StoreError> {
+        self.append(&Entry::Process {
+            id: id.to_string(),
+            process: process.cloned(),
+        })
+    }
+
+    fn create_output(
+        &self,
+        id: &str,
+        attempt: u32,
+        max_size: Option<u64>,
+    ) -> Result<Arc<OutputLog>, StoreError> {
+        create_private_dir(&self.output_dir(id))?;
+        let path = self.output_path(id, attempt);
+        let data = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .mode(FILE_MODE)
+            .open(&path)
+            .map_err(|e| StoreError::io(&path, e))?;
+        let index_path = self.index_path(id, attempt);
+        let index = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(FILE_MODE)
+            .open(&index_path)
+            .map_err(|e| StoreError::io(&index_path, e))?;
+        Ok(Arc::new(
+            OutputLog::spooled(data, Some(index)).with_max_size(max_size),
+        ))
+    }
+
+    fn open_output(&self, id: &str, attempt: u32) -> Result<Arc<OutputLog>, StoreError> {
+        let index_path = self.index_path(id, attempt);
+        let index = match File::open(&index_path) {
+            Ok(index) => Some(index),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(StoreError::io(&index_path, e)),
+        };
+        let path = self.output_path(id, attempt);
+        let output = match File::open(&path) {
+            Ok(data) => OutputLog::load(data, index).map_err(|e| StoreError::io(&path, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => OutputLog::discarded(),
+            Err(e) => return Err(StoreError::io(&path, e)),
+        };
+        Ok(Arc::new(output))
+    }
+
+    fn remove_output(&self, id: &str) -> Result<(), StoreError> {
+        let dir = self.output_dir(id);
+        match fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(StoreError::io(&dir, e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn load(&self) -> Result<Vec<JobRecord>, StoreError> {
+        // Hold the log so that no entry is read half-written.
+        let _log = self.log.lock().unwrap();
+        replay(&self.log_path())
+    }
+}
+
+/// Create `path` and its missing parents, accessible to the server's user only.
+fn create_private_dir(path: &Path) -> Result<(), StoreError> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(DIR_MODE)
+        .create(path)
+        .map_err(|e| StoreError::io(path, e))
+}
+
+/// Create a file in `dir` and unlink it right away, so that its space is
+/// freed once it is closed.
+fn unlinked_file(dir: &Path) -> Result<File, StoreError> {
+    loop {
+        let path = dir.join(format!(".easy_workflow-{:016x}", rand::random::<u64>()));
+        match OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .mode(FILE_MODE)
+            .open(&path)
+        {
+            Ok(file) => {
+                fs::remove_file(&path).map_err(|e| StoreError::io(&path, e))?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(StoreError::io(&path, e)),
+        }
+    }
+}
+
+/// Flush `log` to disk whenever asked to, until every sender is gone.
+/// Requests that pile up during a flush are served by the next one.
+fn sync_log(log: File, path: &Path, requests: mpsc::Receiver<()>) {
+    while requests.recv().is_ok() {
+        while requests.try_recv().is_ok() {}
+        if let Err(e) = log.sync_data() {
+            warn!(?path, "failed to flush job log: {}", e);
+        }
+    }
+}
+
+/// Append `entry` to the log at `path` and flush it to disk.
+fn write_entry(file: &mut File, path: &Path, entry: &Entry) -> Result<(), StoreError> {
+    let mut line = serde_json::to_vec(entry).map_err(StoreError::Encode)?;
+    line.push(b'\n');
+    file.write_all(&line)
+        .and_then(|()| file.sync_data())
+        .map_err(|e| StoreError::io(path, e))
+}
+
+/// Read the log at `path` into the latest record of every job, in the order
+/// the jobs were inserted. Lines that cannot be parsed, such as one cut short
+/// by a crash, are skipped.
+fn replay(path: &Path) -> Result<Vec<JobRecord>, StoreError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(StoreError::io(path, e)),
+    };
+    let mut records: Vec<JobRecord> = Vec::new();
+    let mut index: HashMap<JobId, usize> = HashMap::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| StoreError::io(path, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(Entry::Job(record)) => {
+                index.insert(record.id.clone(), records.len());
+                records.push(*record);
+            }
+            Ok(Entry::Status { id, status }) => match index.get(&id) {
+                Some(&i) => records[i].status = status,
+                None => warn!(?path, line = number + 1, job_id = %id, "status of unknown job"),
+            },
+            Ok(Entry::Process { id, process }) => match index.get(&id) {
+                Some(&i) => records[i].process = process,
+                None => warn!(?path, line = number + 1, job_id = %id, "process of unknown job"),
+            },
+            Err(e) => warn!(
+                ?path,
+                line = number + 1,
+                "skipping unreadable job log entry: {}",
+                e
+            ),
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+    use crate::state::{JobEvent, JobState};
+    use crate::worker::AttemptProcess;
+
+    /// A new directory of the temporary directory, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("store-test-{:016x}", rand::random::<u64>()));
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn record(id: &str) -> JobRecord {
+        JobRecord {
+            id: id.to_string(),
+            spec: JobSpec {
+                owner: "Alice".to_string(),
+                argv: vec!["true".to_string()],
+                ..JobSpec::default()
+            },
+            status: JobStatus::new(),
+            process: None,
+        }
+    }
+
+    fn running() -> JobStatus {
+        let mut status = JobStatus::new();
+        status.apply(JobEvent::Start).unwrap();
+        status
+    }
+
+    fn process() -> AttemptProcess {
+        AttemptProcess {
+            pgid: 42,
+            started: 1234,
+            boot_id: "boot".to_string(),
+            cgroup: None,
+        }
+    }
+
+    fn log_lines(dir: &Path) -> usize {
+        fs::read_to_string(dir.join(LOG_FILE))
+            .unwrap()
+            .lines()
+            .count()
+    }
+
+    #[test]
+    fn replays_the_latest_status_of_every_job_in_insertion_order() {
+        let dir = TempDir::new();
+        {
+            let store = DiskStore::open(&dir.0).unwrap();
+            store.insert(&record("b")).unwrap();
+            store.insert(&record("a")).unwrap();
+            store.update_status("b", &running()).unwrap();
+            store.update_process("b", Some(&process())).unwrap();
+            store.update_status("unknown", &running()).unwrap();
+        }
+        let store = DiskStore::open(&dir.0).unwrap();
+        let records = store.load().unwrap();
+        let ids: Vec<_> = records.iter().map(|record| record.id.as_str()).collect();
+        assert_eq!(ids, ["b", "a"]);
+        assert_eq!(records[0].status.state, JobState::Running);
+        assert_eq!(records[0].process, Some(process()));
+        assert_eq!(records[1].status.state, JobState::Queued);
+        assert_eq!(records[1].process, None);
+
+        store.update_process("b", None).unwrap();
+        assert_eq!(store.load().unwrap()[0].process, None);
+    }
+
+    #[test]
+    fn opening_compacts_the_log_to_a_line_per_job() {
+        let dir = TempDir::new();
+        {
+            let store = DiskStore::open(&dir.0).unwrap();
+            for id in ["a", "b"] {
+                store.insert(&record(id)).unwrap();
+                store.update_status(id, &running()).unwrap();
+            }
+            assert_eq!(log_lines(&dir.0), 4);
+        }
+        let store = DiskStore::open(&dir.0).unwrap();
+        assert_eq!(log_lines(&dir.0), 2);
+        let records = store.load().unwrap();
+        assert!(records
+            .iter()
+            .all(|record| record.status.state == JobState::Running));
+        assert!(!dir.0.join(format!("{}.tmp", LOG_FILE)).exists());
+    }
+
+    #[test]
+    fn skips_a_last_line_cut_short_by_a_crash() {
+        let dir = TempDir::new();
+        {
+            let store = DiskStore::open(&dir.0).unwrap();
+            store.insert(&record("a")).unwrap();
+            store.update_status("a", &running()).unwrap();
+        }
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.0.join(LOG_FILE))
+            .unwrap();
+        log.write_all(br#"{"status":{"id":"a","stat"#).unwrap();
+        drop(log);
+
+        let store = DiskStore::open(&dir.0).unwrap();
+        let records = store.load().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status.state, JobState::Running);
+        // Compaction dropped the cut-off entry, so new entries read fine.
+        store.insert(&record("b")).unwrap();
+        assert_eq!(store.load().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn everything_is_private_to_the_server_user() {
+        let dir = TempDir::new();
+        let store = DiskStore::open(&dir.0).unwrap();
+        store.insert(&record("a")).unwrap();
+        store.create_output("a", 1, None).unwrap();
+        for (path, mode) in [
+            (dir.0.clone(), DIR_MODE),
+            (dir.0.join(OUTPUT_DIR), DIR_MODE),
+            (dir.0.join(LOG_FILE), FILE_MODE),
+            (store.output_dir("a"), DIR_MODE),
+            (store.output_path("a", 1), FILE_MODE),
+            (store.index_path("a", 1), FILE_MODE),
+        ] {
+            let actual = fs::metadata(&path).unwrap().mode() & 0o777;
+            assert_eq!(actual, mode, "{:?}", path);
+        }
+    }
+}
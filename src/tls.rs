@@ -0,0 +1,473 @@
+//! mTLS configuration.
+//!
+//! Both ends build their rustls configuration here instead of relying on
+//! tonic's defaults, so that the protocol versions, cipher suites and
+//! key-exchange groups on the wire are under our control. The default
+//! [`TlsPolicy::Tls13Only`] allows TLS 1.3 alone; [`TlsPolicy::Modern`]
+//! additionally accepts TLS 1.2 with ECDHE and AEAD suites for older peers.
+
+use std::fmt;
+use std::io::{self, BufReader};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{
+    self, cipher_suite, kx_group, version, Certificate, ClientConfig, PrivateKey, RootCertStore,
+    ServerConfig, SupportedCipherSuite, SupportedKxGroup, SupportedProtocolVersion,
+};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+use tracing::{info, warn};
+
+/// Time a client gets to complete the TLS handshake before it is dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed accept, which is most likely out of file
+/// descriptors and would fail again right away.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+const TLS13_SUITES: &[SupportedCipherSuite] = &[
+    cipher_suite::TLS13_AES_256_GCM_SHA384,
+    cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+    cipher_suite::TLS13_AES_128_GCM_SHA256,
+];
+
+const TLS12_SUITES: &[SupportedCipherSuite] = &[
+    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    cipher_suite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    cipher_suite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+];
+
+static TLS13_VERSIONS: &[&SupportedProtocolVersion] = &[&version::TLS13];
+static MODERN_VERSIONS: &[&SupportedProtocolVersion] = &[&version::TLS13, &version::TLS12];
+
+static KX_GROUPS: &[&SupportedKxGroup] = &[
+    &kx_group::X25519,
+    &kx_group::SECP384R1,
+    &kx_group::SECP256R1,
+];
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("failed to read PEM data: {0}")]
+    Pem(#[source] io::Error),
+    #[error("no certificate found in PEM data")]
+    NoCertificate,
+    #[error("no private key found in PEM data")]
+    NoPrivateKey,
+    #[error("cipher suite `{suite}` is not allowed by the {policy} policy")]
+    CipherSuiteNotAllowed { suite: String, policy: TlsPolicy },
+    #[error("unknown TLS policy `{0}` (expected `tls13` or `modern`)")]
+    UnknownPolicy(String),
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Which protocol versions and cipher suites to accept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TlsPolicy {
+    /// TLS 1.3 with AES-GCM and ChaCha20-Poly1305 only.
+    #[default]
+    Tls13Only,
+    /// TLS 1.3, plus TLS 1.2 restricted to ECDHE key exchange and AEAD ciphers.
+    Modern,
+}
+
+impl TlsPolicy {
+    fn protocol_versions(&self) -> &'static [&'static SupportedProtocolVersion] {
+        match self {
+            TlsPolicy::Tls13Only => TLS13_VERSIONS,
+            TlsPolicy::Modern => MODERN_VERSIONS,
+        }
+    }
+
+    fn cipher_suites(&self) -> Vec<SupportedCipherSuite> {
+        match self {
+            TlsPolicy::Tls13Only => TLS13_SUITES.to_vec(),
+            TlsPolicy::Modern => TLS13_SUITES.iter().chain(TLS12_SUITES).copied().collect(),
+        }
+    }
+}
+
+impl FromStr for TlsPolicy {
+    type Err = TlsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tls13" => Ok(TlsPolicy::Tls13Only),
+            "modern" => Ok(TlsPolicy::Modern),
+            other => Err(TlsError::UnknownPolicy(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for TlsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsPolicy::Tls13Only => f.write_str("tls13"),
+            TlsPolicy::Modern => f.write_str("modern"),
+        }
+    }
+}
+
+/// TLS knobs shared by the server and the client.
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    pub policy: TlsPolicy,
+    /// Optional allow-list of cipher suite names (e.g. `TLS13_AES_256_GCM_SHA384`),
+    /// narrowing the suites of `policy`. Empty means every suite of the policy.
+    pub cipher_suites: Vec<String>,
+}
+
+impl TlsSettings {
+    pub(crate) fn selected_cipher_suites(&self) -> Result<Vec<SupportedCipherSuite>, TlsError> {
+        let available = self.policy.cipher_suites();
+        if self.cipher_suites.is_empty() {
+            return Ok(available);
+        }
+        self.cipher_suites
+            .iter()
+            .map(|name| {
+                available
+                    .iter()
+                    .find(|suite| format!("{:?}", suite.suite()) == *name)
+                    .copied()
+                    .ok_or_else(|| TlsError::CipherSuiteNotAllowed {
+                        suite: name.clone(),
+                        policy: self.policy,
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Build the server configuration: our identity, and mandatory client
+/// certificates signed by `client_ca_pem`.
+pub fn server_config(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+    client_ca_pem: &[u8],
+    settings: &TlsSettings,
+) -> Result<ServerConfig, TlsError> {
+    let verifier = AllowAnyAuthenticatedClient::new(load_roots(client_ca_pem)?).boxed();
+    let mut config = ServerConfig::builder()
+        .with_cipher_suites(&settings.selected_cipher_suites()?)
+        .with_kx_groups(KX_GROUPS)
+        .with_protocol_versions(settings.policy.protocol_versions())?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(load_certs(cert_pem)?, load_private_key(key_pem)?)?;
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(config)
+}
+
+/// Build the client configuration: trust `server_ca_pem` and present our
+/// own certificate.
+pub fn client_config(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+    server_ca_pem: &[u8],
+    settings: &TlsSettings,
+) -> Result<ClientConfig, TlsError> {
+    let mut config = ClientConfig::builder()
+        .with_cipher_suites(&settings.selected_cipher_suites()?)
+        .with_kx_groups(KX_GROUPS)
+        .with_protocol_versions(settings.policy.protocol_versions())?
+        .with_root_certificates(load_roots(server_ca_pem)?)
+        .with_client_auth_cert(load_certs(cert_pem)?, load_private_key(key_pem)?)?;
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(config)
+}
+
+/// Accept TCP connections on `listener` and perform the TLS handshake for
+/// each of them in its own task, yielding established streams. Failed
+/// handshakes, and those that take longer than [`HANDSHAKE_TIMEOUT`], are
+/// logged and dropped. The negotiated protocol version and cipher suite are
+/// logged for every connection.
+pub fn incoming(
+    listener: TcpListener,
+    config: ServerConfig,
+) -> ReceiverStream<io::Result<TlsStream<TcpStream>>> {
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        loop {
+            let (tcp, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("failed to accept connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+            if tx.is_closed() {
+                break;
+            }
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                    Ok(Ok(tls)) => {
+                        let (_, conn) = tls.get_ref();
+                        info!(
+                            %peer,
+                            version = ?conn.protocol_version(),
+                            cipher_suite = ?conn.negotiated_cipher_suite().map(|s| s.suite()),
+                            "TLS connection established"
+                        );
+                        let _ = tx.send(Ok(tls)).await;
+                    }
+                    Ok(Err(e)) => warn!(%peer, "TLS handshake failed: {}", e),
+                    Err(_) => warn!(%peer, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+/// Open a gRPC channel to `addr` (`host:port`) through our own TLS
+/// connector, verifying the server certificate against `server_name`.
+pub async fn connect(
+    addr: &str,
+    server_name: &str,
+    config: ClientConfig,
+) -> crate::Result<Channel> {
+    let connector = TlsConnector::from(Arc::new(config));
+    let server_name = rustls::ServerName::try_from(server_name)?;
+    let addr = addr.to_string();
+    // TLS is done by the connector, so tonic itself only sees plain HTTP/2.
+    let channel = Endpoint::from_shared(format!("http://{}", addr))?
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let connector = connector.clone();
+            let server_name = server_name.clone();
+            let addr = addr.clone();
+            async move {
+                let tcp = TcpStream::connect(addr).await?;
+                connector.connect(server_name, tcp).await
+            }
+        }))
+        .await?;
+    Ok(channel)
+}
+
+fn load_certs(pem: &[u8]) -> Result<Vec<Certificate>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(pem)).map_err(TlsError::Pem)?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate);
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(pem: &[u8]) -> Result<PrivateKey, TlsError> {
+    let mut reader = BufReader::new(pem);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader).map_err(TlsError::Pem)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key)
+            | rustls_pemfile::Item::RSAKey(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+    Err(TlsError::NoPrivateKey)
+}
+
+fn load_roots(pem: &[u8]) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(pem)? {
+        roots.add(&cert)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa};
+    use tokio_rustls::rustls::{CipherSuite, ProtocolVersion};
+
+    use super::*;
+
+    /// A fresh CA, with a certificate and key for a `localhost` server and
+    /// one for a client, all in PEM.
+    struct Pki {
+        ca: String,
+        server: (String, String),
+        client: (String, String),
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::new());
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "test CA");
+            let ca = rcgen::Certificate::from_params(params).unwrap();
+            let issue = |params: CertificateParams| {
+                let cert = rcgen::Certificate::from_params(params).unwrap();
+                (
+                    cert.serialize_pem_with_signer(&ca).unwrap(),
+                    cert.serialize_private_key_pem(),
+                )
+            };
+            let server = issue(CertificateParams::new(vec!["localhost".to_string()]));
+            let mut params = CertificateParams::new(Vec::new());
+            params.distinguished_name.push(DnType::CommonName, "Alice");
+            let client = issue(params);
+            Self {
+                ca: ca.serialize_pem().unwrap(),
+                server,
+                client,
+            }
+        }
+    }
+
+    fn settings(policy: TlsPolicy, cipher_suites: &[&str]) -> TlsSettings {
+        TlsSettings {
+            policy,
+            cipher_suites: cipher_suites.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn names(suites: &[SupportedCipherSuite]) -> Vec<String> {
+        suites
+            .iter()
+            .map(|suite| format!("{:?}", suite.suite()))
+            .collect()
+    }
+
+    /// Run a handshake between a server and a client with the given
+    /// settings, returning what they agreed on.
+    async fn handshake(
+        server: &TlsSettings,
+        client: &TlsSettings,
+    ) -> Result<(ProtocolVersion, CipherSuite), io::Error> {
+        let pki = Pki::new();
+        let (cert, key) = &pki.server;
+        let config = server_config(cert.as_bytes(), key.as_bytes(), pki.ca.as_bytes(), server);
+        let acceptor = TlsAcceptor::from(Arc::new(config.unwrap()));
+        let (cert, key) = &pki.client;
+        let config = client_config(cert.as_bytes(), key.as_bytes(), pki.ca.as_bytes(), client);
+        let connector = TlsConnector::from(Arc::new(config.unwrap()));
+        let server_name = rustls::ServerName::try_from("localhost").unwrap();
+        let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+        let (accepted, connected) = tokio::join!(
+            acceptor.accept(server_io),
+            connector.connect(server_name, client_io)
+        );
+        let (accepted, _) = (accepted?, connected?);
+        let (_, conn) = accepted.get_ref();
+        Ok((
+            conn.protocol_version().unwrap(),
+            conn.negotiated_cipher_suite().unwrap().suite(),
+        ))
+    }
+
+    #[test]
+    fn policies_parse_and_display_alike() {
+        for policy in [TlsPolicy::Tls13Only, TlsPolicy::Modern] {
+            assert_eq!(policy.to_string().parse::<TlsPolicy>().unwrap(), policy);
+        }
+        assert_eq!(TlsPolicy::default(), TlsPolicy::Tls13Only);
+        assert!(matches!(
+            "tls12".parse::<TlsPolicy>(),
+            Err(TlsError::UnknownPolicy(_))
+        ));
+    }
+
+    #[test]
+    fn without_an_allow_list_every_suite_of_the_policy_is_selected() {
+        let tls13 = settings(TlsPolicy::Tls13Only, &[]);
+        assert_eq!(
+            names(&tls13.selected_cipher_suites().unwrap()),
+            names(TLS13_SUITES)
+        );
+        let modern = settings(TlsPolicy::Modern, &[]);
+        let selected = modern.selected_cipher_suites().unwrap();
+        assert_eq!(selected.len(), TLS13_SUITES.len() + TLS12_SUITES.len());
+    }
+
+    #[test]
+    fn allow_lists_narrow_the_suites_in_their_order() {
+        let narrowed = settings(
+            TlsPolicy::Modern,
+            &[
+                "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+                "TLS13_CHACHA20_POLY1305_SHA256",
+            ],
+        );
+        assert_eq!(
+            names(&narrowed.selected_cipher_suites().unwrap()),
+            [
+                "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+                "TLS13_CHACHA20_POLY1305_SHA256"
+            ]
+        );
+    }
+
+    #[test]
+    fn allow_lists_cannot_reach_beyond_the_policy() {
+        for suite in [
+            "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+            "TLS_RSA_WITH_AES_128_CBC_SHA",
+            "bogus",
+        ] {
+            let err = settings(TlsPolicy::Tls13Only, &[suite])
+                .selected_cipher_suites()
+                .unwrap_err();
+            assert!(
+                matches!(&err, TlsError::CipherSuiteNotAllowed { suite: s, .. } if s == suite),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn tls13_only_servers_negotiate_tls13() {
+        let server = settings(TlsPolicy::Tls13Only, &[]);
+        let client = settings(TlsPolicy::Modern, &[]);
+        let (version, _) = handshake(&server, &client).await.unwrap();
+        assert_eq!(version, ProtocolVersion::TLSv1_3);
+    }
+
+    #[tokio::test]
+    async fn tls13_only_servers_refuse_tls12_clients() {
+        let server = settings(TlsPolicy::Tls13Only, &[]);
+        let client = settings(
+            TlsPolicy::Modern,
+            &["TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"],
+        );
+        assert!(handshake(&server, &client).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn modern_servers_accept_tls12_clients() {
+        let server = settings(TlsPolicy::Modern, &[]);
+        let client = settings(
+            TlsPolicy::Modern,
+            &["TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"],
+        );
+        let (version, suite) = handshake(&server, &client).await.unwrap();
+        assert_eq!(version, ProtocolVersion::TLSv1_2);
+        assert_eq!(suite, CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384);
+    }
+
+    #[tokio::test]
+    async fn the_negotiated_suite_is_one_both_allow() {
+        let server = settings(TlsPolicy::Tls13Only, &["TLS13_AES_128_GCM_SHA256"]);
+        let client = settings(TlsPolicy::Tls13Only, &[]);
+        let (_, suite) = handshake(&server, &client).await.unwrap();
+        assert_eq!(suite, CipherSuite::TLS13_AES_128_GCM_SHA256);
+    }
+}
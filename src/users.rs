@@ -0,0 +1,156 @@
+//! Unix accounts that jobs run as.
+//!
+//! A [`UserMapping`] maps the certificate CN of the client starting a job,
+//! or failing that one of its roles, to the [`Credentials`] the job runs
+//! with; the worker switches to them right before exec. Jobs of clients
+//! without a mapping run as the server's own user. Only admins may run jobs
+//! as root or with the root group, whether mapped to them or through a
+//! server running as root. Switching users needs a server running as root.
+
+use std::collections::HashMap;
+use std::io;
+
+use nix::unistd::{getegid, geteuid, getgid, getuid, setgid, setgroups, setuid, Gid, Uid};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{AuthError, Role};
+
+/// Order in which roles are looked up for a client holding several.
+const ROLE_PRECEDENCE: [Role; 3] = [Role::Admin, Role::User, Role::Viewer];
+
+/// Identity of a job's processes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary group IDs.
+    #[serde(default)]
+    pub groups: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserMapping {
+    /// Credentials by certificate CN; take precedence over `roles`.
+    pub cns: HashMap<String, Credentials>,
+    /// Credentials by role, for clients whose CN is not mapped. Admin is
+    /// looked up first, then User, then Viewer.
+    pub roles: HashMap<Role, Credentials>,
+}
+
+impl UserMapping {
+    pub fn is_empty(&self) -> bool {
+        self.cns.is_empty() && self.roles.is_empty()
+    }
+
+    /// Credentials for the jobs of the client identified by `cn` and
+    /// `roles`, or `None` to run them as the server's user.
+    pub fn resolve(&self, cn: &str, roles: &[Role]) -> Result<Option<Credentials>, AuthError> {
+        let credentials = self
+            .cns
+            .get(cn)
+            .or_else(|| {
+                ROLE_PRECEDENCE
+                    .iter()
+                    .filter(|role| roles.contains(role))
+                    .find_map(|role| self.roles.get(role))
+            })
+            .cloned();
+        let (uid, gid) = credentials.as_ref().map_or_else(
+            || (geteuid().as_raw(), getegid().as_raw()),
+            |credentials| (credentials.uid, credentials.gid),
+        );
+        if (uid == 0 || gid == 0) && !roles.contains(&Role::Admin) {
+            return Err(AuthError::RootNotAllowed { cn: cn.to_string() });
+        }
+        Ok(credentials)
+    }
+}
+
+/// Switch the calling process to `uid`, `gid` and supplementary `groups`.
+/// Meant to run between fork and exec of a job, after every step that needs
+/// the server's privileges, so it only makes async-signal-safe calls.
+pub(crate) fn drop_privileges(uid: Uid, gid: Gid, groups: &[Gid]) -> io::Result<()> {
+    // setgroups(2) needs CAP_SETGID even to keep the current groups, so a
+    // server that already runs as the target user leaves them as they are.
+    if getuid() == uid && geteuid() == uid && getgid() == gid && getegid() == gid {
+        return Ok(());
+    }
+    setgroups(groups)?;
+    setgid(gid)?;
+    setuid(uid)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(uid: u32, gid: u32) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            groups: Vec::new(),
+        }
+    }
+
+    fn mapping() -> UserMapping {
+        UserMapping {
+            cns: HashMap::from([("Alice".to_string(), credentials(1000, 1000))]),
+            roles: HashMap::from([
+                (Role::Admin, credentials(2000, 2000)),
+                (Role::User, credentials(3000, 3000)),
+            ]),
+        }
+    }
+
+    #[test]
+    fn cns_take_precedence_over_roles() {
+        let resolved = mapping().resolve("Alice", &[Role::Admin]).unwrap();
+        assert_eq!(resolved, Some(credentials(1000, 1000)));
+    }
+
+    #[test]
+    fn roles_are_looked_up_admin_first() {
+        let mapping = mapping();
+        let resolved = mapping.resolve("Bob", &[Role::User, Role::Admin]).unwrap();
+        assert_eq!(resolved, Some(credentials(2000, 2000)));
+        let resolved = mapping.resolve("Bob", &[Role::Viewer, Role::User]).unwrap();
+        assert_eq!(resolved, Some(credentials(3000, 3000)));
+    }
+
+    #[test]
+    fn unmapped_clients_run_as_the_server_user() {
+        let resolved = mapping().resolve("Bob", &[Role::Viewer]);
+        if geteuid().is_root() {
+            assert!(matches!(resolved, Err(AuthError::RootNotAllowed { .. })));
+        } else {
+            assert_eq!(resolved.unwrap(), None);
+        }
+        let resolved = UserMapping::default().resolve("Bob", &[Role::Admin]);
+        assert_eq!(resolved.unwrap(), None);
+    }
+
+    #[test]
+    fn only_admins_may_be_mapped_to_root() {
+        for root in [credentials(0, 1000), credentials(1000, 0)] {
+            let mapping = UserMapping {
+                cns: HashMap::from([("Alice".to_string(), root.clone())]),
+                roles: HashMap::from([(Role::User, root.clone())]),
+            };
+            for (cn, roles) in [("Alice", &[Role::User][..]), ("Bob", &[Role::User])] {
+                let err = mapping.resolve(cn, roles).unwrap_err();
+                assert!(matches!(err, AuthError::RootNotAllowed { cn: ref c } if c == cn));
+            }
+            let resolved = mapping.resolve("Alice", &[Role::User, Role::Admin]);
+            assert_eq!(resolved.unwrap(), Some(root));
+        }
+    }
+
+    #[test]
+    fn keeping_the_current_user_needs_no_privileges() {
+        let groups = [Gid::from_raw(12345)];
+        drop_privileges(geteuid(), getegid(), &groups).unwrap();
+    }
+}
@@ -0,0 +1,1502 @@
+//! Job execution engine.
+//!
+//! The [`Worker`] spawns the processes described by a [`JobSpec`], assigns
+//! each of them a unique job ID and keeps an in-memory job table that the
+//! gRPC service consults. Every job is driven by a supervisor task that waits
+//! for a [`Scheduler`] slot, runs an attempt and, if the attempt failed and
+//! the spec allows it, queues the next attempt after the backoff of the
+//! worker's [`RetryPolicy`].
+//!
+//! Jobs, their status changes and their output are recorded in a
+//! [`JobStore`]; [`Worker::recover`] reloads them after a restart and kills
+//! whatever attempts the previous server left running. The output of
+//! finished jobs is discarded once the worker's [`OutputPolicy`] no longer
+//! retains it.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, UNIX_EPOCH};
+
+use metrics::{counter, gauge};
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{killpg, Signal};
+use nix::sys::wait::{waitid, Id, WaitPidFlag, WaitStatus};
+use nix::unistd::{Gid, Pid, Uid};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::cgroup::{self, CgroupError, CgroupManager, JobCgroup, Quota};
+use crate::events::{EventError, EventLog, EventReader, ResumeToken};
+use crate::isolation::{self, Isolation};
+use crate::labels::{self, LabelError, Selector};
+use crate::output::{OutputLog, OutputPolicy, OutputStream};
+use crate::retry::RetryPolicy;
+use crate::scheduler::{CapacityError, Scheduler, MAX_PRIORITY, MIN_PRIORITY};
+use crate::state::{JobEvent, JobState, JobStatus, ProcessExit, StopOutcome, TransitionError};
+use crate::store::{JobRecord, JobStore, MemoryStore, StoreError};
+use crate::users::{self, Credentials};
+
+pub type JobId = String;
+
+/// Time a job gets to exit after SIGTERM before it is killed, unless the stop
+/// request asks for a different one.
+pub const DEFAULT_STOP_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Time the output pipes of an attempt get to reach EOF once its process has
+/// exited.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Page size of [`Worker::list`] when none is given.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+/// Largest page [`Worker::list`] returns.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Error)]
+pub enum WorkerError {
+    #[error("invalid job spec: {0}")]
+    InvalidSpec(String),
+    #[error("failed to spawn job: {0}")]
+    Spawn(#[source] std::io::Error),
+    #[error("failed to apply resource quota: {0}")]
+    Cgroup(#[from] CgroupError),
+    #[error(transparent)]
+    Capacity(#[from] CapacityError),
+    #[error(transparent)]
+    Label(#[from] LabelError),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error("invalid page token `{0}`")]
+    InvalidPageToken(String),
+    #[error("job {0} not found")]
+    NotFound(JobId),
+    #[error("job {0} is not running")]
+    NotRunning(JobId),
+}
+
+/// Environment a job starts from, before its own variables are set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnvPolicy {
+    /// The server's environment.
+    #[default]
+    Inherit,
+    /// An empty environment.
+    Clear,
+}
+
+/// What to run for a job.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobSpec {
+    /// Certificate CN of the client that submitted the job.
+    pub owner: String,
+    /// Program and its arguments; a program without a slash is looked up in
+    /// `PATH`.
+    pub argv: Vec<String>,
+    /// Run `argv[0]` as a command line with `/bin/sh -c` instead, the rest
+    /// of `argv` becoming its positional parameters.
+    pub shell: bool,
+    /// Absolute directory to run in; `None` keeps the server's.
+    pub working_dir: Option<PathBuf>,
+    /// Fed to the standard input of every attempt, which is empty otherwise.
+    pub stdin: Option<Vec<u8>>,
+    pub env_policy: EnvPolicy,
+    pub envs: Vec<(String, String)>,
+    /// Resource limits, enforced through a per-job cgroup unless unlimited.
+    pub quota: Quota,
+    /// Maximum run time of each attempt, after which it is terminated and
+    /// marked timed out.
+    pub timeout: Option<Duration>,
+    /// How many times to re-run the job after a failed or timed-out attempt.
+    pub retry_count: u32,
+    /// Scheduling priority, from [`MIN_PRIORITY`] to [`MAX_PRIORITY`];
+    /// higher runs first.
+    pub priority: i32,
+    /// Identifying key/value pairs, see [`crate::labels`].
+    pub labels: BTreeMap<String, String>,
+    /// Free-form key/value metadata.
+    pub annotations: BTreeMap<String, String>,
+    /// How each attempt is isolated from the host and other jobs.
+    pub isolation: Isolation,
+    /// Account the job's processes run as; `None` keeps the server's.
+    pub run_as: Option<Credentials>,
+}
+
+impl JobSpec {
+    /// `argv` as a single line, for display.
+    pub fn command_line(&self) -> String {
+        self.argv.join(" ")
+    }
+}
+
+/// Which jobs [`Worker::list`] returns. Unset criteria match every job.
+#[derive(Debug, Clone, Default)]
+pub struct JobFilter {
+    pub selector: Selector,
+    /// Any of these states; empty matches every state.
+    pub states: Vec<JobState>,
+    pub owner: Option<String>,
+}
+
+impl JobFilter {
+    fn matches(&self, job: &Job) -> bool {
+        self.owner
+            .as_ref()
+            .is_none_or(|owner| *owner == job.spec.owner)
+            && (self.states.is_empty() || self.states.contains(&job.status().state))
+            && self.selector.matches(&job.spec.labels)
+    }
+}
+
+/// One page of [`Worker::list`].
+#[derive(Debug, Default)]
+pub struct JobPage {
+    pub jobs: Vec<Arc<Job>>,
+    /// Token for the next page; `None` on the last page.
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    id: JobId,
+    spec: JobSpec,
+    status: watch::Sender<JobStatus>,
+    // Taken by the first stop request; carries the grace period to the supervisor.
+    stop: Mutex<Option<oneshot::Sender<Duration>>>,
+    // Combined stdout and stderr of every attempt, oldest first.
+    outputs: RwLock<Vec<Arc<OutputLog>>>,
+    events: Arc<EventLog>,
+    store: Arc<dyn JobStore>,
+}
+
+impl Job {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn spec(&self) -> &JobSpec {
+        &self.spec
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Move the job through its lifecycle, applying `events` all at once so
+    /// that [`Job::wait`] never sees the intermediate states. Illegal
+    /// transitions leave the status unchanged. Every state entered is
+    /// published to the event log, and the final one is stored.
+    fn transition(
+        &self,
+        events: impl IntoIterator<Item = JobEvent>,
+    ) -> Result<(), TransitionError> {
+        let mut result = Ok(());
+        let mut entered = Vec::new();
+        self.status.send_if_modified(|status| {
+            let mut next = status.clone();
+            for event in events {
+                if let Err(e) = next.apply(event) {
+                    result = Err(e);
+                    return false;
+                }
+                entered.push(next.clone());
+            }
+            *status = next;
+            true
+        });
+        if result.is_ok() {
+            for status in &entered {
+                self.publish(status);
+            }
+            if let Err(e) = self.store.update_status(&self.id, &self.status()) {
+                warn!(job_id = %self.id, "failed to store job status: {}", e);
+            }
+        }
+        result
+    }
+
+    fn publish(&self, status: &JobStatus) {
+        self.events
+            .publish(&self.id, &self.spec.owner, &self.spec.labels, status);
+    }
+
+    /// Position of the job in listings: submission time, then ID. Also the
+    /// page token pointing past it.
+    fn list_key(&self) -> String {
+        let created_at = self.status.borrow().created_at;
+        let nanos = created_at
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos())
+            .unwrap_or_default();
+        format!("{:020}.{}", nanos, self.id)
+    }
+
+    /// Record the process of the running attempt, or that there is none.
+    fn record_process(&self, process: Option<&AttemptProcess>) {
+        if let Err(e) = self.store.update_process(&self.id, process) {
+            warn!(job_id = %self.id, "failed to store job process: {}", e);
+        }
+    }
+
+    /// A new output log for the attempt about to start, kept in memory if
+    /// the store cannot hold it.
+    fn create_output(&self, max_size: Option<u64>) -> Arc<OutputLog> {
+        let attempt = self.status().attempt + 1;
+        let output = self
+            .store
+            .create_output(&self.id, attempt, max_size)
+            .unwrap_or_else(|e| {
+                warn!(job_id = %self.id, "failed to store job output: {}", e);
+                Arc::new(OutputLog::new().with_max_size(max_size))
+            });
+        self.outputs.write().unwrap().push(output.clone());
+        output
+    }
+
+    /// Drop the output of every attempt.
+    fn discard_output(&self) {
+        for output in self.outputs.read().unwrap().iter() {
+            output.discard();
+        }
+        if let Err(e) = self.store.remove_output(&self.id) {
+            warn!(job_id = %self.id, "failed to remove job output: {}", e);
+        }
+        debug!(job_id = %self.id, "discarded job output");
+    }
+
+    /// Output of the latest attempt, if one has started, with the number of
+    /// that attempt.
+    pub fn output(&self) -> Option<(u32, Arc<OutputLog>)> {
+        let outputs = self.outputs.read().unwrap();
+        let output = outputs.last()?.clone();
+        Some((outputs.len() as u32, output))
+    }
+
+    /// Output of attempt `attempt`, counting from 1.
+    pub fn attempt_output(&self, attempt: u32) -> Option<Arc<OutputLog>> {
+        let index = attempt.checked_sub(1)? as usize;
+        self.outputs.read().unwrap().get(index).cloned()
+    }
+
+    /// Wait until the job has finished and return its final status.
+    pub async fn wait(&self) -> JobStatus {
+        let mut status = self.status.subscribe();
+        let finished = status
+            .wait_for(|status| status.state.is_finished())
+            .await
+            .map(|status| status.clone());
+        // The sender lives as long as the job, so waiting cannot fail.
+        finished.unwrap_or_else(|_| self.status())
+    }
+}
+
+/// Finished jobs whose output is still kept, in the order they finished.
+#[derive(Debug, Default)]
+struct Retention {
+    policy: OutputPolicy,
+    jobs: Mutex<VecDeque<Arc<Job>>>,
+}
+
+impl Retention {
+    /// Keep the output of `job`, which has finished, for as long as the
+    /// policy allows. Discards the output of the jobs that finished first
+    /// once too many have.
+    fn retain(self: &Arc<Self>, job: Arc<Job>) {
+        if job
+            .outputs
+            .read()
+            .unwrap()
+            .iter()
+            .all(|output| output.is_discarded())
+        {
+            return;
+        }
+        let evicted: Vec<Arc<Job>> = {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.push_back(job.clone());
+            let excess = self
+                .policy
+                .max_jobs
+                .map_or(0, |max_jobs| jobs.len().saturating_sub(max_jobs));
+            jobs.drain(..excess).collect()
+        };
+        for job in evicted {
+            job.discard_output();
+        }
+
+        if let Some(max_age) = self.policy.max_age {
+            let age = job
+                .status()
+                .finished_at
+                .and_then(|finished_at| finished_at.elapsed().ok())
+                .unwrap_or_default();
+            let retention = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(max_age.saturating_sub(age)).await;
+                retention.expire(&job);
+            });
+        }
+    }
+
+    /// Discard the output of `job`, unless it already was.
+    fn expire(&self, job: &Arc<Job>) {
+        let retained = {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.iter()
+                .position(|retained| Arc::ptr_eq(retained, job))
+                .and_then(|index| jobs.remove(index))
+                .is_some()
+        };
+        if retained {
+            job.discard_output();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Worker {
+    jobs: RwLock<HashMap<JobId, Arc<Job>>>,
+    cgroups: CgroupManager,
+    retry: RetryPolicy,
+    scheduler: Arc<Scheduler>,
+    events: Arc<EventLog>,
+    store: Arc<dyn JobStore>,
+    retention: Arc<Retention>,
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        Self {
+            jobs: RwLock::default(),
+            cgroups: CgroupManager::default(),
+            retry: RetryPolicy::default(),
+            scheduler: Arc::default(),
+            events: Arc::default(),
+            store: Arc::new(MemoryStore::new()),
+            retention: Arc::default(),
+        }
+    }
+}
+
+impl Worker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Space retries of failed jobs according to `retry`.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Run job attempts through `scheduler`.
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = Arc::new(scheduler);
+        self
+    }
+
+    /// Record jobs in `store` instead of in memory only.
+    pub fn with_store(mut self, store: Arc<dyn JobStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Cap and retain job output according to `policy`.
+    pub fn with_output_policy(mut self, policy: OutputPolicy) -> Self {
+        self.retention = Arc::new(Retention {
+            policy,
+            ..Retention::default()
+        });
+        self
+    }
+
+    /// Load the jobs of the store into the job table. Jobs that had not
+    /// finished when the server went down are marked lost, since nothing
+    /// supervises them anymore, and what is left of their processes is
+    /// killed in the background. Returns the number of jobs loaded.
+    pub fn recover(&self) -> Result<usize, WorkerError> {
+        let records = self.store.load()?;
+        let count = records.len();
+        let mut finished = Vec::new();
+        for record in records {
+            let mut outputs = Vec::new();
+            for attempt in 1..=record.status.attempt {
+                outputs.push(self.store.open_output(&record.id, attempt)?);
+            }
+            let job = Arc::new(Job {
+                id: record.id,
+                spec: record.spec,
+                status: watch::Sender::new(record.status),
+                // Recovered jobs have no supervisor left to stop.
+                stop: Mutex::new(None),
+                outputs: RwLock::new(outputs),
+                events: self.events.clone(),
+                store: self.store.clone(),
+            });
+            if let Some(process) = record.process {
+                let job = job.clone();
+                tokio::spawn(async move {
+                    process.kill(&job.id).await;
+                    job.record_process(None);
+                });
+            }
+            let state = job.status().state;
+            if !state.is_finished() {
+                warn!(job_id = %job.id, ?state, "job was lost in a server restart");
+                counter!("lost_jobs_total").increment(1);
+                if let Err(e) = job.transition([JobEvent::Lose]) {
+                    warn!(job_id = %job.id, "{}", e);
+                }
+            }
+            self.jobs
+                .write()
+                .unwrap()
+                .insert(job.id.clone(), job.clone());
+            finished.push(job);
+        }
+        finished.sort_by_key(|job| job.status().finished_at);
+        for job in finished {
+            self.retention.retain(job);
+        }
+        info!(count, "recovered jobs");
+        Ok(count)
+    }
+
+    /// Queue the job described by `spec` and return its ID.
+    pub async fn start(&self, spec: JobSpec) -> Result<JobId, WorkerError> {
+        if spec
+            .argv
+            .first()
+            .is_none_or(|program| program.trim().is_empty())
+        {
+            return Err(WorkerError::InvalidSpec(
+                "argv must not be empty".to_string(),
+            ));
+        }
+        if spec
+            .working_dir
+            .as_ref()
+            .is_some_and(|dir| !dir.is_absolute())
+        {
+            return Err(WorkerError::InvalidSpec(
+                "working_dir must be an absolute path".to_string(),
+            ));
+        }
+        if !(MIN_PRIORITY..=MAX_PRIORITY).contains(&spec.priority) {
+            return Err(WorkerError::InvalidSpec(format!(
+                "priority must be between {} and {}",
+                MIN_PRIORITY, MAX_PRIORITY
+            )));
+        }
+        labels::validate_labels(&spec.labels)?;
+        labels::validate_annotations(&spec.annotations)?;
+        self.scheduler.check_capacity(&spec.quota)?;
+        if !spec.quota.is_unlimited() {
+            self.cgroups.check_available()?;
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let job = Arc::new(Job {
+            id: id.clone(),
+            spec,
+            status: watch::Sender::new(JobStatus::new()),
+            stop: Mutex::new(Some(stop_tx)),
+            outputs: RwLock::new(Vec::new()),
+            events: self.events.clone(),
+            store: self.store.clone(),
+        });
+        self.store.insert(&JobRecord {
+            id: id.clone(),
+            spec: job.spec.clone(),
+            status: job.status(),
+            process: None,
+        })?;
+        self.jobs.write().unwrap().insert(id.clone(), job.clone());
+        job.publish(&job.status());
+        info!(job_id = %id, priority = job.spec.priority, "job queued");
+
+        tokio::spawn(supervise(
+            job,
+            self.cgroups.clone(),
+            self.retry,
+            self.scheduler.clone(),
+            self.retention.clone(),
+            stop_rx,
+        ));
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Arc<Job>, WorkerError> {
+        self.jobs
+            .read()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| WorkerError::NotFound(id.to_string()))
+    }
+
+    /// Jobs matching `filter` in submission order, one page of at most
+    /// `page_size` (or [`DEFAULT_PAGE_SIZE`]) at a time. `page_token` is the
+    /// token returned with the previous page.
+    pub fn list(
+        &self,
+        filter: &JobFilter,
+        page_token: Option<&str>,
+        page_size: Option<usize>,
+    ) -> Result<JobPage, WorkerError> {
+        if let Some(token) = page_token {
+            let valid = token
+                .split_once('.')
+                .is_some_and(|(nanos, id)| nanos.parse::<u128>().is_ok() && !id.is_empty());
+            if !valid {
+                return Err(WorkerError::InvalidPageToken(token.to_string()));
+            }
+        }
+        let page_size = page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        let mut jobs: Vec<(String, Arc<Job>)> = self
+            .jobs
+            .read()
+            .unwrap()
+            .values()
+            .map(|job| (job.list_key(), job.clone()))
+            .filter(|(key, _)| page_token.is_none_or(|token| key.as_str() > token))
+            .collect();
+        jobs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut page = JobPage::default();
+        let mut matching = jobs.into_iter().filter(|(_, job)| filter.matches(job));
+        for (key, job) in matching.by_ref() {
+            page.jobs.push(job);
+            if page.jobs.len() == page_size {
+                page.next_page_token = Some(key);
+                break;
+            }
+        }
+        if matching.next().is_none() {
+            page.next_page_token = None;
+        }
+        Ok(page)
+    }
+
+    /// Stop a job: SIGTERM the process group of its running attempt, wait up
+    /// to `grace_period` (or [`DEFAULT_STOP_GRACE_PERIOD`]) for it to exit,
+    /// then SIGKILL whatever is left. A job waiting for a slot or a retry is
+    /// cancelled. Requests for a job that is already being stopped wait for
+    /// the first one and share its outcome.
+    /// Returns once the job has finished.
+    pub async fn stop(
+        &self,
+        id: &str,
+        grace_period: Option<Duration>,
+    ) -> Result<StopOutcome, WorkerError> {
+        let job = self.get(id)?;
+        if job.status().state.is_finished() {
+            return Err(WorkerError::NotRunning(id.to_string()));
+        }
+        let stop = job.stop.lock().unwrap().take();
+        if let Some(stop) = stop {
+            let grace_period = grace_period.unwrap_or(DEFAULT_STOP_GRACE_PERIOD);
+            info!(job_id = %id, ?grace_period, "stopping job");
+            // If the job finishes on its own meanwhile, the request is simply dropped.
+            let _ = stop.send(grace_period);
+        }
+
+        job.wait()
+            .await
+            .stop_outcome
+            .ok_or_else(|| WorkerError::NotRunning(id.to_string()))
+    }
+
+    /// Number of jobs whose process is still running.
+    pub fn running_count(&self) -> usize {
+        self.jobs
+            .read()
+            .unwrap()
+            .values()
+            .filter(|job| job.status().state == JobState::Running)
+            .count()
+    }
+
+    /// Follow the state changes of every job, starting after event `after`
+    /// or with the next one.
+    pub fn watch(&self, after: Option<ResumeToken>) -> Result<EventReader, EventError> {
+        self.events.subscribe(after)
+    }
+
+    /// 1-based position of job `id` among the attempts waiting for a slot.
+    pub fn queue_position(&self, id: &str) -> Option<usize> {
+        self.scheduler.queue_position(id)
+    }
+}
+
+/// Drive `job` until it has finished, then hand its output over to
+/// `retention`.
+async fn supervise(
+    job: Arc<Job>,
+    cgroups: CgroupManager,
+    retry: RetryPolicy,
+    scheduler: Arc<Scheduler>,
+    retention: Arc<Retention>,
+    stop_rx: oneshot::Receiver<Duration>,
+) {
+    let max_output_size = retention.policy.max_size;
+    run(&job, cgroups, retry, scheduler, max_output_size, stop_rx).await;
+    retention.retain(job);
+}
+
+/// Wait for a slot, run an attempt, and queue the next one after a backoff
+/// as long as failed and timed-out attempts may be retried.
+async fn run(
+    job: &Arc<Job>,
+    cgroups: CgroupManager,
+    retry: RetryPolicy,
+    scheduler: Arc<Scheduler>,
+    max_output_size: Option<u64>,
+    mut stop_rx: oneshot::Receiver<Duration>,
+) {
+    loop {
+        let permit = tokio::select! {
+            permit = scheduler.acquire(&job.id, job.spec.priority, job.spec.quota) => permit,
+            Ok(_) = &mut stop_rx => {
+                if let Err(e) = job.transition([JobEvent::Cancel]) {
+                    warn!(job_id = %job.id, "{}", e);
+                }
+                return;
+            }
+        };
+        // An attempt that cannot even be spawned counts as failed, and is
+        // retried like any other.
+        let (ending, exit) = match RunningAttempt::spawn(job, &cgroups, max_output_size).await {
+            Ok(attempt) => attempt.wait(job, &mut stop_rx).await,
+            Err(e) => {
+                record_start_failure(job, &e);
+                (Ending::Exited, ProcessExit::default())
+            }
+        };
+        drop(permit);
+
+        let event = match ending {
+            Ending::Exited => JobEvent::Exit(exit),
+            Ending::Stopped(outcome) => JobEvent::Stop { outcome, exit },
+            Ending::TimedOut => JobEvent::TimeOut(exit),
+        };
+        let number = job.status().attempt;
+        let retry_left = number <= job.spec.retry_count;
+        if matches!(ending, Ending::Stopped(_)) || exit.success() || !retry_left {
+            if let Err(e) = job.transition([event]) {
+                warn!(job_id = %job.id, "{}", e);
+            }
+            return;
+        }
+        if let Err(e) = job.transition([event, JobEvent::Retry]) {
+            warn!(job_id = %job.id, "{}", e);
+            return;
+        }
+
+        let backoff = retry.backoff(number);
+        info!(job_id = %job.id, attempt = number, ?backoff, "retrying failed job");
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            Ok(_) = &mut stop_rx => {
+                if let Err(e) = job.transition([JobEvent::Cancel]) {
+                    warn!(job_id = %job.id, "{}", e);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Record the start of an attempt whose process could not be spawned, with
+/// the reason as its output.
+fn record_start_failure(job: &Job, err: &WorkerError) {
+    warn!(job_id = %job.id, "failed to start job: {}", err);
+    let output = job.create_output(None);
+    output.append(
+        OutputStream::Stderr,
+        format!("failed to start job: {}\n", err).as_bytes(),
+    );
+    output.close();
+    if let Err(e) = job.transition([JobEvent::Start]) {
+        warn!(job_id = %job.id, "{}", e);
+    }
+}
+
+/// Why the supervisor stopped waiting for an attempt's process.
+#[derive(Debug, Clone, Copy)]
+enum Ending {
+    Exited,
+    Stopped(StopOutcome),
+    TimedOut,
+}
+
+/// The process of one attempt, with everything that has to be cleaned up
+/// once it exits.
+struct RunningAttempt {
+    child: Child,
+    cgroup: Option<JobCgroup>,
+    pumps: Vec<JoinHandle<()>>,
+    output: Arc<OutputLog>,
+}
+
+impl RunningAttempt {
+    /// Spawn the next attempt of `job` and mark the job running. Its output
+    /// is truncated at `max_output_size` bytes.
+    async fn spawn(
+        job: &Arc<Job>,
+        cgroups: &CgroupManager,
+        max_output_size: Option<u64>,
+    ) -> Result<Self, WorkerError> {
+        let spec = &job.spec;
+        let cgroup = if spec.quota.is_unlimited() {
+            None
+        } else {
+            Some(cgroups.create(&job.id, &spec.quota)?)
+        };
+
+        let (program, args) = spec
+            .argv
+            .split_first()
+            .ok_or_else(|| WorkerError::InvalidSpec("argv must not be empty".to_string()))?;
+        let mut command = if spec.shell {
+            let mut command = Command::new("/bin/sh");
+            // "sh" fills in $0 so that `args` start at $1.
+            command.arg("-c").arg(program).arg("sh");
+            command
+        } else {
+            Command::new(program)
+        };
+        if spec.env_policy == EnvPolicy::Clear {
+            command.env_clear();
+        }
+        command
+            .args(args)
+            .envs(spec.envs.iter().map(|(k, v)| (k, v)))
+            .stdin(if spec.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Lead a new process group so that stopping the job reaches every
+            // process it started.
+            .process_group(0);
+        if let Some(cgroup) = &cgroup {
+            let procs = cgroup.procs().try_clone().map_err(WorkerError::Spawn)?;
+            // SAFETY: the closure only issues a write(2) on an already open
+            // file descriptor, which is async-signal-safe.
+            unsafe {
+                command.pre_exec(move || (&procs).write_all(b"0"));
+            }
+        }
+        if spec.isolation == Isolation::Namespaces {
+            let hostname = job.id.clone();
+            // SAFETY: `enter_namespaces` only makes async-signal-safe calls.
+            // It runs after joining the cgroup, so that every process it
+            // forks is accounted there too.
+            unsafe {
+                command.pre_exec(move || isolation::enter_namespaces(&hostname));
+            }
+        }
+        if let Some(credentials) = &spec.run_as {
+            let uid = Uid::from_raw(credentials.uid);
+            let gid = Gid::from_raw(credentials.gid);
+            let groups: Vec<Gid> = credentials
+                .groups
+                .iter()
+                .copied()
+                .map(Gid::from_raw)
+                .collect();
+            // SAFETY: `drop_privileges` only makes async-signal-safe calls.
+            // It runs last, as joining the cgroup and entering namespaces
+            // need the server's privileges.
+            unsafe {
+                command.pre_exec(move || users::drop_privileges(uid, gid, &groups));
+            }
+        }
+        if let Some(dir) = &spec.working_dir {
+            let dir = CString::new(dir.as_os_str().as_bytes()).map_err(|_| {
+                WorkerError::InvalidSpec("working_dir must not contain NUL bytes".to_string())
+            })?;
+            // SAFETY: chdir(2) is async-signal-safe. It runs after dropping
+            // privileges so that the job cannot start out in a directory its
+            // user may not enter.
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::chdir(dir.as_ptr()) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                if let Some(cgroup) = cgroup {
+                    cgroup.remove().await;
+                }
+                return Err(WorkerError::Spawn(e));
+            }
+        };
+
+        if let (Some(data), Some(mut pipe)) = (spec.stdin.clone(), child.stdin.take()) {
+            let job_id = job.id.clone();
+            tokio::spawn(async move {
+                // A job may well exit without reading all of its input.
+                if let Err(e) = pipe.write_all(&data).await {
+                    if e.kind() != io::ErrorKind::BrokenPipe {
+                        warn!(%job_id, "failed to write job input: {}", e);
+                    }
+                }
+            });
+        }
+
+        let output = job.create_output(max_output_size);
+        let pumps = [
+            child
+                .stdout
+                .take()
+                .map(|pipe| pump(pipe, output.clone(), OutputStream::Stdout)),
+            child
+                .stderr
+                .take()
+                .map(|pipe| pump(pipe, output.clone(), OutputStream::Stderr)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if let Err(e) = job.transition([JobEvent::Start]) {
+            warn!(job_id = %job.id, "{}", e);
+        }
+        // Counts attempts whose process has not been reaped yet.
+        gauge!("active_jobs").increment(1);
+        info!(job_id = %job.id, pid = ?child.id(), attempt = job.status().attempt, "job started");
+        if let Some(pid) = child.id() {
+            match AttemptProcess::new(pid, cgroup.as_ref()) {
+                Ok(process) => job.record_process(Some(&process)),
+                Err(e) => warn!(job_id = %job.id, "failed to identify job process: {}", e),
+            }
+        }
+
+        Ok(Self {
+            child,
+            cgroup,
+            pumps,
+            output,
+        })
+    }
+
+    /// Wait for the process to exit, to be stopped or to time out, then
+    /// collect its output and release its cgroup.
+    async fn wait(
+        mut self,
+        job: &Job,
+        stop_rx: &mut oneshot::Receiver<Duration>,
+    ) -> (Ending, ProcessExit) {
+        let pgid = self.child.id().map(|pid| Pid::from_raw(pid as i32));
+        let timeout = job.spec.timeout;
+        let deadline = async {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        let child = &self.child;
+        let ending = tokio::select! {
+            exited = leader_exited(child) => {
+                if let Err(e) = exited {
+                    warn!(job_id = %job.id, "failed to wait for job: {}", e);
+                }
+                Ending::Exited
+            }
+            Ok(grace_period) = stop_rx => {
+                Ending::Stopped(terminate(&job.id, child, grace_period).await)
+            }
+            _ = deadline => {
+                info!(job_id = %job.id, ?timeout, "job timed out");
+                counter!("timed_out_jobs_total").increment(1);
+                terminate(&job.id, child, DEFAULT_STOP_GRACE_PERIOD).await;
+                Ending::TimedOut
+            }
+        };
+        // The attempt ends with its leader: kill whatever it left running in
+        // the background, which would otherwise hold the pipes open. The
+        // leader is not reaped yet, so the group ID cannot have been reused.
+        signal_group(&job.id, pgid, Signal::SIGKILL);
+        let exit = match self.child.wait().await {
+            Ok(status) => ProcessExit::from(status),
+            Err(e) => {
+                warn!(job_id = %job.id, "failed to wait for job: {}", e);
+                ProcessExit::default()
+            }
+        };
+        gauge!("active_jobs").decrement(1);
+        debug!(job_id = %job.id, ?exit, ?ending, "job attempt finished");
+
+        // Drain whatever is still buffered in the pipes before closing the
+        // log. Processes that left the group may still hold them open, so
+        // give up on those after a while.
+        let drained_by = tokio::time::Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+        for mut pump in self.pumps {
+            if tokio::time::timeout_at(drained_by, &mut pump)
+                .await
+                .is_err()
+            {
+                warn!(job_id = %job.id, "job output still open after the job exited");
+                pump.abort();
+            }
+        }
+        self.output.close();
+        if let Some(cgroup) = self.cgroup {
+            cgroup.remove().await;
+        }
+        job.record_process(None);
+        (ending, exit)
+    }
+}
+
+/// The process group of an attempt, identified well enough for a later
+/// server to tell whether it is still around.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttemptProcess {
+    /// ID of the group, which is the PID of its leader.
+    pub pgid: i32,
+    /// Start time of the leader, in clock ticks since boot.
+    pub started: u64,
+    /// Boot the leader was started in.
+    pub boot_id: String,
+    /// Leaf cgroup of the attempt, if it has one.
+    pub cgroup: Option<PathBuf>,
+}
+
+impl AttemptProcess {
+    fn new(pid: u32, cgroup: Option<&JobCgroup>) -> io::Result<Self> {
+        let pgid = pid as i32;
+        Ok(Self {
+            pgid,
+            started: process_start_time(pgid)?,
+            boot_id: boot_id()?,
+            cgroup: cgroup.map(|cgroup| cgroup.path().to_path_buf()),
+        })
+    }
+
+    /// Kill what is left of the attempt once the server that ran it is gone.
+    /// The group is only signalled while its leader is provably the same
+    /// process, as its ID may have been reused since.
+    async fn kill(&self, job_id: &str) {
+        let same_leader = boot_id().is_ok_and(|boot_id| boot_id == self.boot_id)
+            && process_start_time(self.pgid).is_ok_and(|started| started == self.started);
+        if same_leader {
+            warn!(
+                job_id,
+                pgid = self.pgid,
+                "killing processes left behind by the previous server"
+            );
+            signal_group(job_id, Some(Pid::from_raw(self.pgid)), Signal::SIGKILL);
+        }
+        if let Some(path) = &self.cgroup {
+            cgroup::remove_leaf(path).await;
+        }
+    }
+}
+
+/// Start time of process `pid`, in clock ticks since boot.
+fn process_start_time(pid: i32) -> io::Result<u64> {
+    let path = format!("/proc/{}/stat", pid);
+    let stat = std::fs::read_to_string(&path)?;
+    // The command name may contain spaces and parentheses, the fields after
+    // it cannot. The start time is the 22nd field, the 20th after the name.
+    stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(19))
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed {}", path)))
+}
+
+fn boot_id() -> io::Result<String> {
+    let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id")?;
+    Ok(boot_id.trim().to_string())
+}
+
+/// SIGTERM the job's process group and give its leader `grace_period` to exit
+/// before escalating to SIGKILL. The leader is left for the caller to reap.
+async fn terminate(job_id: &str, child: &Child, grace_period: Duration) -> StopOutcome {
+    let pgid = child.id().map(|pid| Pid::from_raw(pid as i32));
+    signal_group(job_id, pgid, Signal::SIGTERM);
+    match tokio::time::timeout(grace_period, leader_exited(child)).await {
+        Ok(Ok(WaitStatus::Signaled(..))) => StopOutcome::Terminated,
+        Ok(_) => StopOutcome::ExitedCleanly,
+        Err(_) => {
+            warn!(job_id, ?grace_period, "job ignored SIGTERM, killing it");
+            signal_group(job_id, pgid, Signal::SIGKILL);
+            if let Err(e) = leader_exited(child).await {
+                warn!(job_id, "failed to wait for job: {}", e);
+            }
+            StopOutcome::Killed
+        }
+    }
+}
+
+/// Wait for the leader of an attempt to exit without reaping it. Until it is
+/// reaped, neither its PID nor the ID of its process group can be reused, so
+/// the group can still be signalled safely.
+async fn leader_exited(child: &Child) -> io::Result<WaitStatus> {
+    let pid = child
+        .id()
+        .ok_or_else(|| io::Error::other("job process was already reaped"))?;
+    let pid = Pid::from_raw(pid as i32);
+    // Subscribe before looking, so that an exit in between is not missed.
+    let mut exits = signal(SignalKind::child())?;
+    let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT | WaitPidFlag::WNOHANG;
+    loop {
+        match waitid(Id::Pid(pid), flags) {
+            Ok(WaitStatus::StillAlive) => {}
+            Ok(status) => return Ok(status),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+        if exits.recv().await.is_none() {
+            return Err(io::Error::other("SIGCHLD stream ended"));
+        }
+    }
+}
+
+fn signal_group(job_id: &str, pgid: Option<Pid>, signal: Signal) {
+    let Some(pgid) = pgid else {
+        return;
+    };
+    match killpg(pgid, signal) {
+        // The group is already empty.
+        Ok(()) | Err(Errno::ESRCH) => {}
+        Err(e) => warn!(job_id, ?signal, "failed to signal job process group: {}", e),
+    }
+}
+
+/// Copy everything read from `pipe` into `log` as output of `stream` until EOF.
+fn pump<R>(mut pipe: R, log: Arc<OutputLog>, stream: OutputStream) -> JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 8192];
+        loop {
+            match pipe.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    log.append(stream, &buf[..n]);
+                }
+                Err(e) => {
+                    warn!("failed to read job output: {}", e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+    use super::*;
+
+    /// A worker holding `count` finished jobs, submitted a second apart,
+    /// with every other one labelled `parity=even`.
+    fn worker_with_jobs(count: usize) -> Worker {
+        let store = MemoryStore::new();
+        for i in 0..count {
+            let mut labels = BTreeMap::new();
+            if i % 2 == 0 {
+                labels.insert("parity".to_string(), "even".to_string());
+            }
+            let status = JobStatus {
+                state: JobState::Succeeded,
+                created_at: UNIX_EPOCH + Duration::from_secs(i as u64 + 1),
+                ..JobStatus::new()
+            };
+            let record = JobRecord {
+                id: format!("job-{}", i),
+                spec: JobSpec {
+                    owner: "Alice".to_string(),
+                    argv: vec!["true".to_string()],
+                    labels,
+                    ..JobSpec::default()
+                },
+                status,
+                process: None,
+            };
+            store.insert(&record).unwrap();
+        }
+        let worker = Worker::default().with_store(Arc::new(store));
+        worker.recover().unwrap();
+        worker
+    }
+
+    fn ids(page: &JobPage) -> Vec<&str> {
+        page.jobs.iter().map(|job| job.id()).collect()
+    }
+
+    /// Every page of `filter`, following the page tokens.
+    fn all_pages(worker: &Worker, filter: &JobFilter, page_size: usize) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut token = None;
+        loop {
+            let page = worker
+                .list(filter, token.as_deref(), Some(page_size))
+                .unwrap();
+            pages.push(ids(&page).into_iter().map(str::to_string).collect());
+            match page.next_page_token {
+                Some(next) => token = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_jobs_in_submission_order() {
+        let worker = worker_with_jobs(3);
+        let page = worker.list(&JobFilter::default(), None, None).unwrap();
+        assert_eq!(ids(&page), ["job-0", "job-1", "job-2"]);
+        assert_eq!(page.next_page_token, None);
+    }
+
+    #[tokio::test]
+    async fn pages_follow_each_other() {
+        let worker = worker_with_jobs(5);
+        let pages = all_pages(&worker, &JobFilter::default(), 2);
+        assert_eq!(
+            pages,
+            [
+                vec!["job-0", "job-1"],
+                vec!["job-2", "job-3"],
+                vec!["job-4"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_last_page_that_is_exactly_full_has_no_token() {
+        let worker = worker_with_jobs(4);
+        let pages = all_pages(&worker, &JobFilter::default(), 2);
+        assert_eq!(pages, [vec!["job-0", "job-1"], vec!["job-2", "job-3"]]);
+
+        let page = worker.list(&JobFilter::default(), None, Some(4)).unwrap();
+        assert_eq!(page.jobs.len(), 4);
+        assert_eq!(page.next_page_token, None);
+    }
+
+    #[tokio::test]
+    async fn filters_apply_across_pages() {
+        let worker = worker_with_jobs(6);
+        let filter = JobFilter {
+            selector: Selector::parse("parity=even").unwrap(),
+            ..JobFilter::default()
+        };
+        let pages = all_pages(&worker, &filter, 2);
+        assert_eq!(pages, [vec!["job-0", "job-2"], vec!["job-4"]]);
+        // The page fills up with the last even job, which is followed by an
+        // odd one: that must not yield an empty extra page.
+        let pages = all_pages(&worker, &filter, 3);
+        assert_eq!(pages, [vec!["job-0", "job-2", "job-4"]]);
+
+        let filter = JobFilter {
+            owner: Some("Bob".to_string()),
+            ..JobFilter::default()
+        };
+        assert!(all_pages(&worker, &filter, 2)[0].is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_page_tokens() {
+        let worker = worker_with_jobs(1);
+        for token in ["", "abc", "12.", "x.job-0"] {
+            let err = worker
+                .list(&JobFilter::default(), Some(token), None)
+                .unwrap_err();
+            assert!(matches!(err, WorkerError::InvalidPageToken(_)), "{}", err);
+        }
+    }
+
+    /// A job of Alice running `script`.
+    fn shell_job(script: &str) -> JobSpec {
+        JobSpec {
+            owner: "Alice".to_string(),
+            argv: vec![script.to_string()],
+            shell: true,
+            ..JobSpec::default()
+        }
+    }
+
+    /// Run `script` as a job of Alice on `worker` and wait for it to finish.
+    async fn run(worker: &Worker, script: &str) -> Arc<Job> {
+        let job = worker
+            .get(&worker.start(shell_job(script)).await.unwrap())
+            .unwrap();
+        job.wait().await;
+        job
+    }
+
+    /// Wait for the output of `job` to be discarded.
+    async fn discarded(job: &Job) {
+        let output = job.output().unwrap().1;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !output.is_discarded() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("job output was not discarded");
+    }
+
+    #[tokio::test]
+    async fn output_of_the_jobs_that_finished_first_is_evicted() {
+        let worker = Worker::default().with_output_policy(OutputPolicy {
+            max_jobs: Some(2),
+            ..OutputPolicy::default()
+        });
+        let mut jobs = Vec::new();
+        for i in 0..3 {
+            jobs.push(run(&worker, &format!("echo {}", i)).await);
+        }
+        discarded(&jobs[0]).await;
+        for job in &jobs[1..] {
+            assert!(!job.output().unwrap().1.is_discarded());
+        }
+    }
+
+    #[tokio::test]
+    async fn output_is_discarded_after_the_retention_period() {
+        let worker = Worker::default().with_output_policy(OutputPolicy {
+            max_age: Some(Duration::from_millis(50)),
+            ..OutputPolicy::default()
+        });
+        let job = run(&worker, "echo hello").await;
+        discarded(&job).await;
+    }
+
+    #[tokio::test]
+    async fn job_output_is_capped() {
+        let worker = Worker::default().with_output_policy(OutputPolicy {
+            max_size: Some(4),
+            ..OutputPolicy::default()
+        });
+        let job = run(&worker, "echo hello world").await;
+        let output = job.output().unwrap().1;
+        assert!(output.is_truncated());
+        assert_eq!(
+            output.len(),
+            4 + "\n[output truncated at 4 bytes]\n".len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_jobs_are_retried_retry_count_times() {
+        let worker = Worker::default().with_retry_policy(RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            multiplier: 1.0,
+            jitter: 0.0,
+        });
+        let spec = JobSpec {
+            owner: "Alice".to_string(),
+            argv: vec!["false".to_string()],
+            retry_count: 2,
+            ..JobSpec::default()
+        };
+        let job = worker.get(&worker.start(spec).await.unwrap()).unwrap();
+        job.wait().await;
+
+        let status = job.status();
+        assert_eq!(status.state, JobState::Failed);
+        assert_eq!(status.exit_code, Some(1));
+        assert_eq!(status.attempt, 3);
+        let attempts: Vec<_> = status
+            .attempts
+            .iter()
+            .map(|attempt| (attempt.number, attempt.state, attempt.exit_code))
+            .collect();
+        assert_eq!(
+            attempts,
+            [1, 2, 3].map(|number| (number, JobState::Failed, Some(1)))
+        );
+    }
+
+    /// A worker recovering a job that was running `process` when the
+    /// previous server went down.
+    async fn recover_running(process: AttemptProcess) -> (Worker, Arc<MemoryStore>) {
+        let store = Arc::new(MemoryStore::new());
+        let mut status = JobStatus::new();
+        status.apply(JobEvent::Start).unwrap();
+        store
+            .insert(&JobRecord {
+                id: "job-0".to_string(),
+                spec: JobSpec::default(),
+                status,
+                process: Some(process),
+            })
+            .unwrap();
+        let worker = Worker::default().with_store(store.clone());
+        worker.recover().unwrap();
+        (worker, store)
+    }
+
+    fn leftover() -> std::process::Child {
+        std::process::Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn recovery_kills_the_processes_of_lost_jobs() {
+        let mut child = leftover();
+        let process = AttemptProcess::new(child.id(), None).unwrap();
+        let (worker, store) = recover_running(process).await;
+        assert_eq!(worker.get("job-0").unwrap().status().state, JobState::Lost);
+
+        let status = tokio::task::spawn_blocking(move || child.wait())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while store.load().unwrap()[0].process.is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the killed process was not forgotten");
+    }
+
+    #[tokio::test]
+    async fn recovery_spares_a_group_whose_leader_is_another_process() {
+        let mut child = leftover();
+        let mut process = AttemptProcess::new(child.id(), None).unwrap();
+        process.started -= 1;
+        recover_running(process).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    /// Start `spec` and wait for the first line of its output, which the
+    /// test scripts print once they are set up.
+    async fn started(worker: &Worker, spec: JobSpec) -> (Arc<Job>, String) {
+        let job = worker.get(&worker.start(spec).await.unwrap()).unwrap();
+        let line = tokio::time::timeout(Duration::from_secs(5), async {
+            let output = loop {
+                match job.output() {
+                    Some((_, output)) => break output,
+                    None => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            };
+            let mut reader = output.subscribe();
+            let mut data = Vec::new();
+            while !data.contains(&b'\n') {
+                data.extend(reader.next_chunk().await.expect("job ended early").data);
+            }
+            let line = data.split(|&b| b == b'\n').next().unwrap().to_vec();
+            String::from_utf8(line).unwrap()
+        })
+        .await
+        .expect("job did not get ready");
+        (job, line)
+    }
+
+    /// Wait for every process of the group led by the `$$` a script printed
+    /// to be gone.
+    async fn group_gone(pgid: &str) {
+        let pgid = Pid::from_raw(pgid.parse().unwrap());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while killpg(pgid, None) != Err(Errno::ESRCH) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("job processes outlived the job");
+    }
+
+    #[tokio::test]
+    async fn stopping_a_job_terminates_its_process_group() {
+        let worker = Worker::default();
+        let (job, pgid) = started(&worker, shell_job("sleep 30 & echo $$; wait")).await;
+        let outcome = worker.stop(job.id(), None).await.unwrap();
+        assert_eq!(outcome, StopOutcome::Terminated);
+        let status = job.status();
+        assert_eq!(status.state, JobState::Stopped);
+        assert_eq!(status.signal, Some(libc::SIGTERM));
+        group_gone(&pgid).await;
+    }
+
+    #[tokio::test]
+    async fn jobs_may_exit_on_their_own_terms_when_stopped() {
+        let worker = Worker::default();
+        let (job, pgid) = started(
+            &worker,
+            shell_job("trap 'exit 3' TERM; sleep 30 & echo $$; wait"),
+        )
+        .await;
+        let outcome = worker.stop(job.id(), None).await.unwrap();
+        assert_eq!(outcome, StopOutcome::ExitedCleanly);
+        assert_eq!(job.status().exit_code, Some(3));
+        group_gone(&pgid).await;
+    }
+
+    #[tokio::test]
+    async fn stopping_escalates_to_sigkill_after_the_grace_period() {
+        let worker = Worker::default();
+        let (job, pgid) =
+            started(&worker, shell_job("trap '' TERM; sleep 30 & echo $$; wait")).await;
+        let grace_period = Duration::from_millis(200);
+        let stopping = tokio::time::Instant::now();
+        let outcome = worker.stop(job.id(), Some(grace_period)).await.unwrap();
+        assert!(stopping.elapsed() >= grace_period);
+        assert_eq!(outcome, StopOutcome::Killed);
+        let status = job.status();
+        assert_eq!(status.state, JobState::Stopped);
+        assert_eq!(status.signal, Some(libc::SIGKILL));
+        group_gone(&pgid).await;
+    }
+
+    #[tokio::test]
+    async fn processes_left_behind_by_the_leader_are_killed() {
+        let worker = Worker::default();
+        let (job, pgid) = started(&worker, shell_job("sleep 30 & echo $$")).await;
+        assert_eq!(job.wait().await.state, JobState::Succeeded);
+        group_gone(&pgid).await;
+    }
+
+    #[tokio::test]
+    async fn jobs_running_past_their_timeout_are_terminated() {
+        let worker = Worker::default();
+        let spec = JobSpec {
+            timeout: Some(Duration::from_millis(200)),
+            ..shell_job("sleep 30 & echo $$; wait")
+        };
+        let (job, pgid) = started(&worker, spec).await;
+        let status = job.wait().await;
+        assert_eq!(status.state, JobState::TimedOut);
+        assert_eq!(status.signal, Some(libc::SIGTERM));
+        assert_eq!(status.stop_outcome, None);
+        group_gone(&pgid).await;
+    }
+}